@@ -0,0 +1,46 @@
+//! Times blocking parses of a small form, to compare the Tokio runtime with the minimal
+//! executor used when built with `default-features = false`:
+//!
+//! ```text
+//! cargo bench --bench small_form
+//! cargo bench --bench small_form --no-default-features
+//! ```
+
+use multipart_rs_multer::{form, Options};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 100_000;
+
+fn main() {
+    let content_type = "multipart/form-data; boundary=X";
+    let body = "--X\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nAda\r\n\
+                --X\r\nContent-Disposition: form-data; name=\"email\"\r\n\r\nada@example.com\r\n\
+                --X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                Content-Type: text/plain\r\n\r\nhello\r\n--X--\r\n";
+    let options = Options::default();
+
+    // Warm up, which also starts the calling thread's runtime.
+    for _ in 0..1_000 {
+        black_box(form::parse_blocking(content_type, body, &options).unwrap());
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(form::parse_blocking(content_type, body, &options).unwrap());
+    }
+    let elapsed = start.elapsed();
+
+    let executor = if cfg!(feature = "tokio") {
+        "tokio"
+    } else {
+        "futures executor"
+    };
+    println!(
+        "{}: {} parses in {:?}, {:.2} µs per parse",
+        executor,
+        ITERATIONS,
+        elapsed,
+        elapsed.as_secs_f64() * 1e6 / f64::from(ITERATIONS)
+    );
+}
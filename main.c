@@ -1,44 +1,9 @@
 // Example usage of this library to parse a multipart form data
 #include <assert.h>
 #include <stdio.h>
-#include <string.h>
 
 #include "multipart_rs_multer.h"
 
-// Get the index of the file by field name
-int get_file_index(const FormData* data, const char* field_name) {
-    for (uintptr_t i = 0; i < data->file_count; i++) {
-        if (strcmp(data->files[i].field_name, field_name) == 0) {
-            return (int)i;
-        }
-    }
-    return -1;
-}
-
-// get the value of the field by name
-const char* get_field_value(const FormData* data, const char* field_name) {
-    for (uintptr_t i = 0; i < data->field_count; i++) {
-        if (strcmp(data->fields[i].name, field_name) == 0) {
-            return data->fields[i].value;
-        }
-    }
-    return NULL;
-}
-
-// Get file indices by field name if multiple files are uploaded with the same field name
-void get_file_indices(const FormData* data, const char* field_name, int* indices_arr, size_t array_size,
-                      size_t* count) {
-    *count = 0;
-    for (uintptr_t i = 0; i < data->file_count; i++) {
-        if (strcmp(data->files[i].field_name, field_name) == 0) {
-            if (*count >= array_size) {
-                break;
-            }
-            indices_arr[(*count)++] = (int)i;
-        }
-    }
-}
-
 int main() {
     char* body =
         "----WebKitFormBoundaryak4VBVRUB0vxEAhj\r\n"
@@ -71,8 +36,11 @@ int main() {
 
     // Parse the multipart form data
     FormData* data = parse_multipart_form_data(body);
+    if (data == NULL) {
+        fprintf(stderr, "Failed to parse form data (%d): %s\n", multipart_last_error(), multipart_last_error_message());
+        return 1;
+    }
 
-    assert(data != NULL);
     assert(data->field_count == 2);
     assert(data->file_count == 2);
     assert(data->fields != NULL);
@@ -88,28 +56,29 @@ int main() {
                data->files[i].content_type, data->files[i].content_length);
     }
 
-    // Get the file index by field name
-    int file_index = get_file_index(data, "file");
-    assert(file_index != -1);
-    printf("File index: %d\n", file_index);
+    // Get the first file by field name
+    MultipartFile* file = form_data_get_file(data, "file");
+    assert(file != NULL);
+    printf("First file: %s\n", file->filename);
 
     // Get the value of the field by name
-    const char* username = get_field_value(data, "username");
+    const char* username = form_data_get_field(data, "username");
     assert(username != NULL);
     printf("Username: %s\n", username);
 
-    const char* password = get_field_value(data, "password");
+    const char* password = form_data_get_field(data, "password");
     assert(password != NULL);
     printf("Password: %s\n", password);
 
-    // Get file indices by field name
-    int file_indices[2];
-    size_t count = 0;
+    assert(form_data_has(data, "username"));
+    assert(!form_data_has(data, "email"));
 
-    get_file_indices(data, "file", file_indices, 2, &count);
+    // Get every file uploaded with the same field name
+    MultipartFile* files[2];
+    size_t count = form_data_get_files(data, "file", files, 2);
 
     assert(count == 2);
-    printf("File indices: %d, %d\n", file_indices[0], file_indices[1]);
+    printf("Files: %s, %s\n", files[0]->filename, files[1]->filename);
 
     // Free the form data
     free_multipart_form_data(data);
@@ -0,0 +1,197 @@
+use crate::error::{Error, MultipartError};
+use std::alloc::{self, Layout};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+/// Alignment of the allocation, and the largest alignment of a struct placed in it: that of
+/// pointers and sizes, or of the 64-bit keys of the name index on 32-bit targets.
+const ALIGN: usize = max(std::mem::align_of::<usize>(), std::mem::align_of::<u64>());
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// A single allocation that holds structs at its start, followed by bytes.
+///
+/// The layout is computed twice by the same code: first against a measuring arena, which
+/// places nothing and only counts the space needed, then against an arena of exactly that
+/// size. Pointers returned while measuring are dangling and must not be dereferenced.
+pub(crate) struct Arena {
+    base: *mut u8,      // Start of the allocation, or null while measuring.
+    structs: usize,     // Offset after the last struct placed so far.
+    bytes: usize,       // Offset after the last byte placed so far, from `bytes_start`.
+    bytes_start: usize, // Offset of the bytes, after every struct.
+    capacity: usize,    // Size of the allocation, or zero while measuring.
+}
+
+impl Arena {
+    /// Creates an arena that only counts the space needed by the layout.
+    pub fn measure() -> Self {
+        Arena {
+            base: std::ptr::null_mut(),
+            structs: 0,
+            bytes: 0,
+            bytes_start: 0,
+            capacity: 0,
+        }
+    }
+
+    /// Allocates an arena with exactly the space counted by `measured`.
+    pub fn allocate(measured: &Arena) -> Result<Self, Error> {
+        let layout = layout(measured.size())?;
+        let base = unsafe { alloc::alloc(layout) };
+        if base.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        Ok(Arena {
+            base,
+            structs: 0,
+            bytes: 0,
+            bytes_start: measured.structs,
+            capacity: measured.size(),
+        })
+    }
+
+    /// Size of the allocation, or of the space counted so far while measuring.
+    pub fn size(&self) -> usize {
+        if self.base.is_null() {
+            self.structs + self.bytes
+        } else {
+            self.capacity
+        }
+    }
+
+    /// Whether the layout written so far fills the allocation exactly, as it does once the
+    /// layout that was measured has been written again.
+    pub fn is_full(&self) -> bool {
+        !self.base.is_null()
+            && self.structs == self.bytes_start
+            && self.bytes_start + self.bytes == self.capacity
+    }
+
+    /// Reserves space for `count` structs of type `T`, to be written with `write`.
+    pub fn reserve<T>(&mut self, count: usize) -> *mut T {
+        const { assert!(std::mem::align_of::<T>() <= ALIGN) };
+
+        let offset = self.structs.next_multiple_of(std::mem::align_of::<T>());
+        self.structs = offset + std::mem::size_of::<T>() * count;
+        assert!(self.base.is_null() || self.structs <= self.bytes_start);
+        self.base.wrapping_add(offset) as *mut T
+    }
+
+    /// Writes `value` to a slot returned by `reserve`. Does nothing while measuring.
+    pub fn write<T>(&mut self, slot: *mut T, value: T) {
+        if !self.base.is_null() {
+            unsafe { slot.write(value) };
+        }
+    }
+
+    /// Places the items one after the other and returns the first.
+    pub fn alloc_slice<T>(&mut self, items: Vec<T>) -> *mut T {
+        let slice = self.reserve::<T>(items.len());
+        for (i, item) in items.into_iter().enumerate() {
+            self.write(slice.wrapping_add(i), item);
+        }
+        slice
+    }
+
+    /// Copies the bytes into the arena.
+    pub fn alloc_bytes(&mut self, bytes: &[u8]) -> *mut u8 {
+        let offset = self.bytes_start + self.bytes;
+        self.bytes += bytes.len();
+        assert!(self.base.is_null() || self.bytes_start + self.bytes <= self.capacity);
+
+        let ptr = self.base.wrapping_add(offset);
+        if !self.base.is_null() {
+            unsafe { ptr.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+        }
+        ptr
+    }
+
+    /// Copies the string into the arena, with its terminator.
+    pub fn alloc_c_str(&mut self, string: &CStr) -> *const c_char {
+        self.alloc_bytes(string.to_bytes_with_nul()) as *const c_char
+    }
+
+    /// Copies the bytes into the arena followed by a NUL terminator. Unlike a C string,
+    /// the bytes may contain NULs themselves.
+    pub fn alloc_c_buffer(&mut self, bytes: &[u8]) -> *const c_char {
+        let buffer = self.alloc_bytes(bytes);
+        self.alloc_bytes(&[0]);
+        buffer as *const c_char
+    }
+}
+
+fn layout(size: usize) -> Result<Layout, Error> {
+    Layout::from_size_align(size, ALIGN)
+        .map_err(|_| Error::new(MultipartError::Internal, "form data is too large"))
+}
+
+/// Frees an allocation of `size` bytes made by `Arena::allocate`.
+///
+/// # Safety
+/// `base` must be the start of an arena allocated with `size` bytes that has not been freed.
+pub(crate) unsafe fn free(base: *mut u8, size: usize) {
+    if let Ok(layout) = layout(size) {
+        alloc::dealloc(base, layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Places structs of every alignment, interleaved with bytes, and returns the pointers.
+    fn lay_out(arena: &mut Arena) -> (*mut u8, *mut u64, *mut u16, *mut u8, *const c_char) {
+        let flags = arena.alloc_slice(vec![1u8, 2, 3]);
+        let words = arena.alloc_slice(vec![u64::MAX, 7]);
+        let bytes = arena.alloc_bytes(b"abc");
+        let half = arena.reserve::<u16>(1);
+        arena.write(half, 0xbeef);
+        let buffer = arena.alloc_c_buffer(b"x\0y");
+        (flags, words, half, bytes, buffer)
+    }
+
+    #[test]
+    fn measuring_and_writing_agree() {
+        let mut measured = Arena::measure();
+        lay_out(&mut measured);
+        let mut arena = Arena::allocate(&measured).unwrap();
+        assert!(!arena.is_full());
+        let (flags, words, half, bytes, buffer) = lay_out(&mut arena);
+        assert!(arena.is_full());
+        assert_eq!(arena.size(), measured.size());
+        // 3 flags and their padding, 2 words and a half, then 3 bytes and 4 of the buffer.
+        let padded = 3usize.next_multiple_of(std::mem::align_of::<u64>());
+        assert_eq!(arena.size(), padded + 16 + 2 + 3 + 4);
+
+        unsafe {
+            assert_eq!(flags, arena.base);
+            assert_eq!(words as usize % std::mem::align_of::<u64>(), 0);
+            assert_eq!(half as usize % std::mem::align_of::<u16>(), 0);
+            assert_eq!(std::slice::from_raw_parts(flags, 3), [1, 2, 3]);
+            assert_eq!(std::slice::from_raw_parts(words, 2), [u64::MAX, 7]);
+            assert_eq!(*half, 0xbeef);
+            assert_eq!(std::slice::from_raw_parts(bytes, 3), b"abc");
+            assert_eq!(
+                std::slice::from_raw_parts(buffer as *const u8, 4),
+                b"x\0y\0"
+            );
+            free(arena.base, arena.size());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn writing_more_than_was_measured_panics() {
+        let mut measured = Arena::measure();
+        measured.alloc_bytes(b"abc");
+        let mut arena = Arena::allocate(&measured).unwrap();
+        arena.alloc_bytes(b"abcd");
+    }
+}
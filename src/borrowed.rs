@@ -0,0 +1,308 @@
+use crate::error::{ffi_guard, Error, MultipartError};
+use crate::form::{self, Form};
+use crate::hash::Digest;
+use crate::options::{Options, ParseOptions};
+use crate::sink::{FormBuilder, PartMeta, PartSink};
+use crate::spool::FileContent;
+use crate::{body_slice, content_type_str, FormData};
+use memchr::memmem;
+use multer::bytes::Bytes;
+use std::ops::Range;
+use std::os::raw::c_char;
+
+/// Finds where the content of each part lies in a complete body, in order, following the
+/// same rules as multer: the first delimiter may be preceded by a preamble, may be followed
+/// by spaces and tabs, and each part's content ends at the next CRLF and delimiter.
+/// Stops at the first part that cannot be located; the parser reports the actual error.
+fn content_ranges(body: &[u8], boundary: &str) -> Vec<Range<usize>> {
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n--{}", boundary);
+    let next_delimiter = memmem::Finder::new(next_delimiter.as_bytes());
+    let mut ranges = Vec::new();
+
+    let mut pos = match memmem::find(body, delimiter.as_bytes()) {
+        Some(start) => start + delimiter.len(),
+        None => return ranges,
+    };
+
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            return ranges;
+        }
+
+        let padding = rest
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if !rest[padding..].starts_with(b"\r\n") {
+            return ranges;
+        }
+        pos += padding + 2;
+
+        let start = match memmem::find(&body[pos..], b"\r\n\r\n") {
+            Some(headers_len) => pos + headers_len + 4,
+            None => return ranges,
+        };
+        let end = match next_delimiter.find(&body[start..]) {
+            Some(content_len) => start + content_len,
+            None => return ranges,
+        };
+
+        ranges.push(start..end);
+        pos = end + 2 + delimiter.len();
+    }
+}
+
+/// Collects every part like `FormCollector`, but slices each part's content from the body
+/// instead of copying it.
+pub(crate) struct SliceCollector {
+    builder: FormBuilder,
+    body: Bytes,
+    ranges: std::vec::IntoIter<Range<usize>>,
+    current: Option<(PartMeta, Option<Range<usize>>, usize)>,
+}
+
+impl SliceCollector {
+    pub fn new(body: Bytes, boundary: &str) -> Self {
+        let ranges = content_ranges(&body, boundary).into_iter();
+
+        SliceCollector {
+            builder: FormBuilder::default(),
+            body,
+            ranges,
+            current: None,
+        }
+    }
+}
+
+/// The parser read a part that the body scan did not find where expected.
+fn not_located() -> Error {
+    Error::new(
+        MultipartError::Internal,
+        "part content could not be located in the body",
+    )
+}
+
+impl PartSink for SliceCollector {
+    fn begin(&mut self, part: PartMeta) -> Result<(), Error> {
+        // A part missing from the scan is only reported once it has been read in full, so
+        // that a truncated or malformed body fails with the parser's own error.
+        self.current = Some((part, self.ranges.next(), 0));
+        Ok(())
+    }
+
+    fn data(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        // Only the length is needed to check that the parser read the same content.
+        if let Some((_, _, len)) = &mut self.current {
+            *len += chunk.len();
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, digest: Option<Digest>) -> Result<(), Error> {
+        if let Some((part, range, len)) = self.current.take() {
+            let range = range
+                .filter(|range| range.len() == len)
+                .ok_or_else(not_located)?;
+
+            let content = self.body.slice(range);
+            self.builder
+                .push(part, FileContent::Memory(content), digest);
+        }
+        Ok(())
+    }
+
+    fn finish(self, options: &Options) -> Result<Form, Error> {
+        self.builder.finish(options)
+    }
+}
+
+/// Parses the multipart form data like `parse_multipart_form_data_with_options`, except that
+/// file contents and field values point into `body` instead of being copied; only names,
+/// filenames, content types and headers are allocated by the library.
+/// The body must therefore outlive the returned form data. Field values point to the raw bytes
+/// as received: `value` and `raw_value` are the same, `value` is not NUL-terminated and is not
+/// transcoded, so use `value_len`, and look fields up with `form_data_get_field_n` and
+/// `form_data_get_fields_all_n`; the NUL-terminated lookups fail with `BorrowedValue`.
+/// Files are never spooled to disk, and nested multipart parts are not expanded.
+/// Returns null on failure; see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`,
+/// and must not free the body before then.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+/// `body` must be null or point to at least `len` readable bytes that stay valid and unchanged
+/// until the form data is freed.
+/// `options` must be null or point to a valid `ParseOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data_borrowed(
+    content_type: *const c_char,
+    body: *const u8,
+    len: usize,
+    options: *const ParseOptions,
+) -> *mut FormData {
+    ffi_guard(|| {
+        let content_type = content_type_str(content_type)?;
+        let body = body_slice(body, len)?;
+        let options = Options::from_ffi(options)?;
+
+        let form = form::parse_borrowed(content_type, Bytes::from_static(body), &options)?;
+        // The contents are slices of the caller's body, so they are handed over as they are.
+        FormData::from_form(form, true)
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::multipart_last_error;
+    use crate::lookup::{form_data_get_field, form_data_get_field_n, form_data_get_fields_all};
+    use crate::{free_multipart_form_data, parse_multipart_form_data_with_options};
+    use std::ffi::CStr;
+    use std::ptr;
+
+    const CONTENT_TYPE: &CStr = c"multipart/form-data; boundary=X";
+
+    /// Parses the body both borrowed and owned, checking that every borrowed value and
+    /// content lies inside the body and matches what the owned parse copied.
+    fn parse_both(body: &[u8]) -> Result<usize, (MultipartError, MultipartError)> {
+        let content_type = CONTENT_TYPE.as_ptr();
+        let (start, end) = (body.as_ptr() as usize, body.as_ptr() as usize + body.len());
+        let inside = |ptr: *const u8, len: usize| {
+            len == 0 || (ptr as usize >= start && ptr as usize + len <= end)
+        };
+
+        unsafe {
+            let borrowed = parse_multipart_form_data_borrowed(
+                content_type,
+                body.as_ptr(),
+                body.len(),
+                ptr::null(),
+            );
+            let borrowed_error = multipart_last_error();
+            let owned = parse_multipart_form_data_with_options(
+                content_type,
+                body.as_ptr(),
+                body.len(),
+                ptr::null(),
+            );
+            let owned_error = multipart_last_error();
+            let (borrowed, owned) = match (borrowed.as_ref(), owned.as_ref()) {
+                (Some(borrowed), Some(owned)) => (borrowed, owned),
+                _ => {
+                    free_multipart_form_data(borrowed);
+                    free_multipart_form_data(owned);
+                    return Err((borrowed_error, owned_error));
+                }
+            };
+
+            assert!(borrowed.borrowed && !owned.borrowed);
+            assert_eq!(borrowed.field_count, owned.field_count);
+            assert_eq!(borrowed.file_count, owned.file_count);
+
+            for i in 0..owned.field_count {
+                let (field, expected) = (&*borrowed.fields.add(i), &*owned.fields.add(i));
+                assert_eq!(CStr::from_ptr(field.name), CStr::from_ptr(expected.name));
+                assert!(!field.value.is_null());
+                assert!(inside(field.value as *const u8, field.value_len));
+                assert_eq!(field.value as *const u8, field.raw_value);
+                let value = std::slice::from_raw_parts(field.value as *const u8, field.value_len);
+                let raw = std::slice::from_raw_parts(expected.raw_value, expected.raw_value_len);
+                assert_eq!(value, raw);
+            }
+
+            for i in 0..owned.file_count {
+                let (file, expected) = (&*borrowed.files.add(i), &*owned.files.add(i));
+                assert_eq!(
+                    CStr::from_ptr(file.filename),
+                    CStr::from_ptr(expected.filename)
+                );
+                assert!(!file.content.is_null());
+                assert!(inside(file.content, file.content_length));
+                let content = std::slice::from_raw_parts(file.content, file.content_length);
+                let copied = std::slice::from_raw_parts(expected.content, expected.content_length);
+                assert_eq!(content, copied);
+            }
+
+            let parts = owned.field_count + owned.file_count;
+            free_multipart_form_data(borrowed as *const FormData as *mut FormData);
+            free_multipart_form_data(owned as *const FormData as *mut FormData);
+            Ok(parts)
+        }
+    }
+
+    #[test]
+    fn values_and_contents_point_into_the_body() {
+        let body = b"--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nalpha\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"f\"; filename=\"f.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n\x00\xff\r\n--\r\n-X\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\xe9t\xe9\r\n--X--\r\n";
+        assert_eq!(parse_both(body), Ok(3));
+    }
+
+    #[test]
+    fn preamble_and_epilogue() {
+        let body = b"This is a preamble, mentioning --Y.\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nalpha\r\n\
+            --X--\r\nThis is an epilogue.\r\n";
+        assert_eq!(parse_both(body), Ok(1));
+    }
+
+    #[test]
+    fn transport_padding() {
+        let body = b"--X \t\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nalpha\r\n\
+            --X\t \r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nbeta\r\n--X--\r\n";
+        assert_eq!(parse_both(body), Ok(2));
+    }
+
+    #[test]
+    fn empty_values() {
+        let body = b"--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"f\"; filename=\"\"\r\n\r\n\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nbeta\r\n--X--\r\n";
+        assert_eq!(parse_both(body), Ok(3));
+    }
+
+    #[test]
+    fn lf_only_line_endings() {
+        // Delimiters must follow a CRLF, so both parses reject the body alike.
+        let body = b"--X\nContent-Disposition: form-data; name=\"a\"\n\nalpha\n\
+            --X\nContent-Disposition: form-data; name=\"b\"\n\nbeta\n--X--\n";
+        let incomplete = MultipartError::IncompleteStream;
+        assert_eq!(parse_both(body), Err((incomplete, incomplete)));
+
+        // And a delimiter after a lone LF is part of the content.
+        let body = b"--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\
+            one\n--X\ntwo\n--X--\n\r\n--X--\r\n";
+        assert_eq!(parse_both(body), Ok(1));
+    }
+
+    #[test]
+    fn nul_terminated_lookups_fail() {
+        let body = b"--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nalpha\r\n--X--\r\n";
+        unsafe {
+            let data = parse_multipart_form_data_borrowed(
+                CONTENT_TYPE.as_ptr(),
+                body.as_ptr(),
+                body.len(),
+                ptr::null(),
+            );
+            let name = c"a".as_ptr();
+
+            assert!(form_data_get_field(data, name).is_null());
+            assert_eq!(multipart_last_error(), MultipartError::BorrowedValue);
+            assert_eq!(form_data_get_fields_all(data, name, ptr::null_mut(), 0), 0);
+            assert_eq!(multipart_last_error(), MultipartError::BorrowedValue);
+
+            let mut len = 0;
+            let value = form_data_get_field_n(data, name, &mut len);
+            assert_eq!(
+                std::slice::from_raw_parts(value as *const u8, len),
+                b"alpha"
+            );
+            free_multipart_form_data(data);
+        }
+    }
+}
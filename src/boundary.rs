@@ -0,0 +1,76 @@
+use crate::error::{Error, MultipartError};
+use mime::Mime;
+
+/// Maximum boundary length allowed by RFC 2046.
+const MAX_BOUNDARY_LEN: usize = 70;
+
+/// Returns true if the boundary is 1-70 characters long, contains only the
+/// characters allowed by RFC 2046 and does not end with a space.
+pub fn is_valid_boundary(boundary: &str) -> bool {
+    if boundary.is_empty() || boundary.len() > MAX_BOUNDARY_LEN || boundary.ends_with(' ') {
+        return false;
+    }
+
+    boundary.bytes().all(|b| {
+        b.is_ascii_alphanumeric()
+            || matches!(
+                b,
+                b'\''
+                    | b'('
+                    | b')'
+                    | b'+'
+                    | b'_'
+                    | b','
+                    | b'-'
+                    | b'.'
+                    | b'/'
+                    | b':'
+                    | b'='
+                    | b'?'
+                    | b' '
+            )
+    })
+}
+
+/// Extracts the boundary from a `multipart/form-data` Content-Type header value.
+/// Fails if the header is not multipart/form-data or the boundary is missing or invalid.
+pub fn parse_boundary(content_type: &str) -> Result<String, Error> {
+    let boundary = multer::parse_boundary(content_type)?;
+    if !is_valid_boundary(&boundary) {
+        return Err(Error::new(
+            MultipartError::MissingBoundary,
+            format!("invalid multipart boundary: {:?}", boundary),
+        ));
+    }
+
+    Ok(boundary)
+}
+
+/// Returns the boundary of a nested multipart part, such as `multipart/mixed`, from its
+/// Content-Type, or `None` if the part is not multipart or its boundary is missing or invalid.
+pub fn nested_boundary(content_type: &Mime) -> Option<&str> {
+    if content_type.type_() != mime::MULTIPART {
+        return None;
+    }
+
+    content_type
+        .get_param(mime::BOUNDARY)
+        .map(|boundary| boundary.as_str())
+        .filter(|boundary| is_valid_boundary(boundary))
+}
+
+/// Guesses the boundary from the first delimiter line of the body.
+/// Any preamble before the first line starting with `--` is skipped.
+pub fn sniff_boundary(body: &[u8]) -> Option<&str> {
+    let line = body
+        .split(|&b| b == b'\n')
+        .find(|line| line.starts_with(b"--"))?;
+
+    // Strip the leading '--' and the trailing '\r' if present.
+    let line = &line[2..];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    std::str::from_utf8(line)
+        .ok()
+        .filter(|boundary| is_valid_boundary(boundary))
+}
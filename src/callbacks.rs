@@ -0,0 +1,201 @@
+use crate::error::{ffi_guard, ffi_status, Error, MultipartError};
+use crate::form::{parse_parts, Form};
+use crate::hash::Digest;
+use crate::options::{Options, ParseOptions};
+use crate::parser::MultipartParser;
+use crate::sink::{PartMeta, PartSink};
+use crate::{block_on, body_slice, body_stream, content_type_boundary, PartHeader};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+
+/// Describes a part to `on_part_begin`. Every pointer is only valid during the callback.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PartInfo {
+    name: *const c_char,                  // Name of the field.
+    filename: *const c_char,              // Filename of the file, or null for text fields.
+    content_type: *const c_char,          // Content type of the part, or null if it has none.
+    headers: *const PartHeader,           // Array of every header of the part.
+    header_count: usize,                  // Number of headers in the array.
+    detected_content_type: *const c_char, // Detected type, or null for text fields.
+}
+
+/// Called once the headers of a part have been read. For files, it is only called once the
+/// start of the content has also been read, to detect its content type.
+pub type OnPartBegin = unsafe extern "C" fn(context: *mut c_void, part: *const PartInfo) -> c_int;
+
+/// Called for each chunk of the current part's content. The chunk is only valid during the callback.
+pub type OnPartData =
+    unsafe extern "C" fn(context: *mut c_void, data: *const u8, len: usize) -> c_int;
+
+/// Called after the last chunk of the current part.
+pub type OnPartEnd = unsafe extern "C" fn(context: *mut c_void) -> c_int;
+
+/// Callbacks invoked as parts are parsed, instead of collecting them into a `FormData`.
+/// Any callback may be null. Returning non-zero from a callback aborts the parse with `Aborted`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MultipartCallbacks {
+    on_part_begin: Option<OnPartBegin>,
+    on_part_data: Option<OnPartData>,
+    on_part_end: Option<OnPartEnd>,
+    context: *mut c_void, // Passed as the first argument to every callback.
+}
+
+/// Forwards every part to the C callbacks without buffering its content.
+pub(crate) struct CallbackSink {
+    callbacks: MultipartCallbacks,
+}
+
+impl CallbackSink {
+    /// Copies the callbacks passed from C.
+    ///
+    /// # Safety
+    /// `callbacks` must be null or point to a valid `MultipartCallbacks`.
+    pub unsafe fn from_ffi(callbacks: *const MultipartCallbacks) -> Result<Self, Error> {
+        let callbacks = callbacks
+            .as_ref()
+            .ok_or_else(|| Error::new(MultipartError::NullArgument, "callbacks is null"))?;
+
+        Ok(CallbackSink {
+            callbacks: *callbacks,
+        })
+    }
+}
+
+/// Maps a callback's return value onto the parse result.
+fn check_status(status: c_int, callback: &str) -> Result<(), Error> {
+    if status != 0 {
+        return Err(Error::new(
+            MultipartError::Aborted,
+            format!("parse aborted by {} (status {})", callback, status),
+        ));
+    }
+    Ok(())
+}
+
+impl PartSink for CallbackSink {
+    fn begin(&mut self, part: PartMeta) -> Result<(), Error> {
+        let on_part_begin = match self.callbacks.on_part_begin {
+            Some(on_part_begin) => on_part_begin,
+            None => return Ok(()),
+        };
+
+        // Keep the C strings alive until the callback returns.
+        let name = CString::new(part.name)?;
+        let filename = part
+            .file_name
+            .map(|file_name| CString::new(file_name.decoded))
+            .transpose()?;
+        let content_type = part
+            .content_type
+            .map(|mime| CString::new(mime.to_string()))
+            .transpose()?;
+        let detected_content_type = part.detected_content_type.map(CString::new).transpose()?;
+        let header_strings = part
+            .headers
+            .into_iter()
+            .map(|(name, value)| Ok((CString::new(name)?, CString::new(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let headers: Vec<PartHeader> = header_strings
+            .iter()
+            .map(|(name, value)| PartHeader {
+                name: name.as_ptr(),
+                value: value.as_ptr(),
+            })
+            .collect();
+
+        let info = PartInfo {
+            name: name.as_ptr(),
+            filename: filename.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            content_type: content_type
+                .as_ref()
+                .map_or(std::ptr::null(), |s| s.as_ptr()),
+            headers: headers.as_ptr(),
+            header_count: headers.len(),
+            detected_content_type: detected_content_type
+                .as_ref()
+                .map_or(std::ptr::null(), |s| s.as_ptr()),
+        };
+
+        let status = unsafe { on_part_begin(self.callbacks.context, &info) };
+        check_status(status, "on_part_begin")
+    }
+
+    fn data(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        match self.callbacks.on_part_data {
+            Some(on_part_data) => {
+                let status =
+                    unsafe { on_part_data(self.callbacks.context, chunk.as_ptr(), chunk.len()) };
+                check_status(status, "on_part_data")
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn end(&mut self, _digest: Option<Digest>) -> Result<(), Error> {
+        match self.callbacks.on_part_end {
+            Some(on_part_end) => {
+                let status = unsafe { on_part_end(self.callbacks.context) };
+                check_status(status, "on_part_end")
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn finish(self, _options: &Options) -> Result<Form, Error> {
+        Ok(Form::default())
+    }
+}
+
+/// Parses the multipart form data like `parse_multipart_form_data_with_options`, but hands
+/// each part to `callbacks` as it is read instead of collecting the parts in memory.
+/// A null `options` applies no limits and keeps every file in memory.
+/// Returns `Ok` on success, or the reason the parse failed; `Aborted` if a callback returned non-zero.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+/// `body` must be null or point to at least `len` readable bytes.
+/// `options` must be null or point to a valid `ParseOptions`.
+/// `callbacks` must be null or point to a valid `MultipartCallbacks`.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data_with_callbacks(
+    content_type: *const c_char,
+    body: *const u8,
+    len: usize,
+    options: *const ParseOptions,
+    callbacks: *const MultipartCallbacks,
+) -> MultipartError {
+    ffi_status(|| {
+        let boundary = content_type_boundary(content_type)?;
+        let body = body_slice(body, len)?;
+        let options = Options::from_ffi(options)?;
+        let sink = CallbackSink::from_ffi(callbacks)?;
+
+        block_on(parse_parts(body_stream(body), &boundary, &options, sink))??;
+        Ok(())
+    })
+}
+
+/// Creates an incremental parser like `multipart_parser_new` that hands each part to
+/// `callbacks` as it is read. `multipart_parser_finish` then returns an empty form data.
+///
+/// # Safety
+/// `boundary` must be null or point to a valid NUL-terminated string.
+/// `options` must be null or point to a valid `ParseOptions`.
+/// `callbacks` must be null or point to a valid `MultipartCallbacks`.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_parser_new_with_callbacks(
+    boundary: *const c_char,
+    options: *const ParseOptions,
+    callbacks: *const MultipartCallbacks,
+) -> *mut MultipartParser {
+    let parser = ffi_guard(|| {
+        let sink = CallbackSink::from_ffi(callbacks)?;
+        MultipartParser::from_ffi(boundary, options, |_| sink)
+    });
+
+    parser.map_or(std::ptr::null_mut(), |parser| {
+        Box::into_raw(Box::new(parser))
+    })
+}
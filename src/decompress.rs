@@ -0,0 +1,464 @@
+use crate::error::{Error, MultipartError};
+use crate::options::Options;
+use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState, StandardAlloc};
+use flate2::{Decompress, FlushDecompress, Status};
+use futures::stream::{self, Stream, StreamExt};
+use multer::bytes::Bytes;
+use zstd::stream::raw::{Decoder as ZstdDecoder, InBuffer, Operation, OutBuffer};
+
+/// Size of each block of decompressed output; the size and ratio limits are checked after every
+/// block, so that a zip bomb is stopped before it has been inflated in full.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Content codings that can be decompressed, besides `identity`.
+const CODINGS: &[&str] = &["gzip", "x-gzip", "deflate", "br", "zstd"];
+
+/// Decompressed size up to which the ratio limit is not enforced, so that small but highly
+/// compressible content, such as a file of spaces, is not mistaken for a zip bomb.
+const RATIO_GRACE: u64 = 64 * 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type BrotliDecoder = BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>;
+
+enum Codec {
+    // gzip, and zlib for `deflate`.
+    Flate(Decompress),
+    Brotli(Box<BrotliDecoder>),
+    Zstd(ZstdDecoder<'static>),
+}
+
+/// Progress made by a single call to a codec.
+struct Step {
+    read: usize,
+    written: usize,
+    // Whether the end of the compressed content has been reached.
+    done: bool,
+}
+
+impl Codec {
+    fn step(&mut self, input: &[u8], output: &mut [u8]) -> Result<Step, String> {
+        match self {
+            Codec::Flate(flate) => {
+                let (total_in, total_out) = (flate.total_in(), flate.total_out());
+                let status = flate
+                    .decompress(input, output, FlushDecompress::None)
+                    .map_err(|err| err.to_string())?;
+                Ok(Step {
+                    read: (flate.total_in() - total_in) as usize,
+                    written: (flate.total_out() - total_out) as usize,
+                    done: status == Status::StreamEnd,
+                })
+            }
+            Codec::Brotli(state) => {
+                let (mut available_in, mut input_offset) = (input.len(), 0);
+                let (mut available_out, mut output_offset, mut total_out) = (output.len(), 0, 0);
+                let result = BrotliDecompressStream(
+                    &mut available_in,
+                    &mut input_offset,
+                    input,
+                    &mut available_out,
+                    &mut output_offset,
+                    output,
+                    &mut total_out,
+                    state,
+                );
+                if matches!(result, BrotliResult::ResultFailure) {
+                    return Err("invalid brotli data".to_string());
+                }
+                Ok(Step {
+                    read: input_offset,
+                    written: output_offset,
+                    done: matches!(result, BrotliResult::ResultSuccess),
+                })
+            }
+            Codec::Zstd(zstd) => {
+                let mut in_buffer = InBuffer::around(input);
+                let mut out_buffer = OutBuffer::around(output);
+                // A hint of 0 means that a frame has been decoded and flushed completely;
+                // another frame may still follow.
+                let hint = zstd
+                    .run(&mut in_buffer, &mut out_buffer)
+                    .map_err(|err| err.to_string())?;
+                Ok(Step {
+                    read: in_buffer.pos(),
+                    written: out_buffer.pos(),
+                    done: hint == 0,
+                })
+            }
+        }
+    }
+}
+
+/// Decompresses content sent with a `Content-Encoding` chunk by chunk, enforcing limits on
+/// the decompressed size and on the ratio of decompressed to compressed bytes.
+pub(crate) struct Decompressor {
+    codec: Codec,
+    // Where each step decompresses to, kept so that small chunks do not each pay for a block.
+    block: Box<[u8]>,
+    done: bool,
+    compressed: u64,
+    decompressed: u64,
+    max_size: Option<u64>,
+    max_ratio: Option<u64>,
+    // What is being decompressed, for error messages: the body or a field.
+    subject: String,
+}
+
+impl Decompressor {
+    /// Returns a decompressor for the content coding, or `None` for `identity`, failing with
+    /// `InvalidContentEncoding` if the coding is not supported.
+    /// `deflate` is the zlib format, as specified by RFC 9110 §8.4.1.2.
+    pub fn new(
+        encoding: &str,
+        max_size: Option<u64>,
+        max_ratio: Option<u64>,
+        subject: String,
+    ) -> Result<Option<Self>, Error> {
+        let codec = match encoding.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => return Ok(None),
+            "gzip" | "x-gzip" => Codec::Flate(Decompress::new_gzip(15)),
+            "deflate" => Codec::Flate(Decompress::new(true)),
+            "br" => Codec::Brotli(Box::new(BrotliState::new(
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+            ))),
+            "zstd" => Codec::Zstd(ZstdDecoder::new()?),
+            _ => {
+                return Err(Error::new(
+                    MultipartError::InvalidContentEncoding,
+                    format!(
+                        "{} has an unsupported content encoding: {:?}",
+                        subject, encoding
+                    ),
+                ))
+            }
+        };
+
+        Ok(Some(Decompressor {
+            codec,
+            block: vec![0; BLOCK_SIZE].into_boxed_slice(),
+            done: false,
+            compressed: 0,
+            decompressed: 0,
+            max_size,
+            max_ratio,
+            subject,
+        }))
+    }
+
+    /// Decompresses as much of the content received so far as possible, and everything that
+    /// the codec held back once the `last` chunk has been received, failing then if the
+    /// compressed content was cut short.
+    pub fn decompress(&mut self, mut input: &[u8], last: bool) -> Result<Bytes, Error> {
+        self.compressed += input.len() as u64;
+
+        let mut output = Vec::new();
+        loop {
+            let step = self
+                .codec
+                .step(input, &mut self.block)
+                .map_err(|err| self.invalid(&err))?;
+            output.extend_from_slice(&self.block[..step.written]);
+            input = &input[step.read..];
+
+            // Asking for more output once the content has ended makes no progress.
+            self.done = step.done || (self.done && step.read == 0 && step.written == 0);
+            self.decompressed += step.written as u64;
+            self.check_size()?;
+            self.check_ratio()?;
+
+            // A block that was not filled means that the codec needs more input.
+            if step.written < BLOCK_SIZE {
+                if input.is_empty() {
+                    break;
+                }
+                if step.read == 0 {
+                    return Err(self.invalid("data after the end of the compressed content"));
+                }
+            }
+        }
+
+        if last && !self.done {
+            return Err(self.invalid("compressed content is truncated"));
+        }
+        Ok(output.into())
+    }
+
+    fn check_size(&self) -> Result<(), Error> {
+        match self.max_size {
+            Some(limit) if self.decompressed > limit => Err(Error::new(
+                MultipartError::SizeLimitExceeded,
+                format!("{} exceeded the size limit: {} bytes", self.subject, limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_ratio(&self) -> Result<(), Error> {
+        match self.max_ratio {
+            Some(ratio)
+                if self.decompressed > RATIO_GRACE
+                    && self.decompressed > self.compressed.saturating_mul(ratio) =>
+            {
+                Err(Error::new(
+                    MultipartError::SizeLimitExceeded,
+                    format!(
+                        "{} exceeded the decompression ratio limit: {}",
+                        self.subject, ratio
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn invalid(&self, reason: &str) -> Error {
+        Error::new(
+            MultipartError::InvalidContentEncoding,
+            format!("{} could not be decompressed: {}", self.subject, reason),
+        )
+    }
+}
+
+/// Returns true if content sent with the content coding can be decompressed.
+pub(crate) fn is_supported(encoding: &str) -> bool {
+    CODINGS.contains(&encoding.trim().to_ascii_lowercase().as_str())
+}
+
+/// Returns a decompressor for the body, or `None` if the options give it no content encoding.
+/// The decompressed body may be no larger than `max_total_size`.
+pub(crate) fn body_decompressor(options: &Options) -> Result<Option<Decompressor>, Error> {
+    match &options.content_encoding {
+        Some(encoding) => Decompressor::new(
+            encoding,
+            options.max_total_size,
+            options.max_decompression_ratio,
+            "body".to_string(),
+        ),
+        None => Ok(None),
+    }
+}
+
+/// Decompresses the chunks of `stream` as they are read. The stream ends after the first error.
+pub(crate) fn decompress_stream<S>(
+    stream: S,
+    decompressor: Decompressor,
+) -> impl Stream<Item = Result<Bytes, BoxError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Send + 'static,
+{
+    stream::unfold(
+        (Box::pin(stream), Some(decompressor)),
+        |(mut stream, decompressor)| async move {
+            let mut decompressor = decompressor?;
+            let (chunk, last) = match stream.next().await {
+                Some(Ok(chunk)) => (chunk, false),
+                Some(Err(err)) => return Some((Err(err), (stream, None))),
+                None => (Bytes::new(), true),
+            };
+
+            match decompressor.decompress(&chunk, last) {
+                Ok(chunk) if !last => Some((Ok(chunk), (stream, Some(decompressor)))),
+                Ok(chunk) => Some((Ok(chunk), (stream, None))),
+                Err(err) => Some((Err(err.into()), (stream, None))),
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn compress(encoding: &str, data: &[u8]) -> Vec<u8> {
+        match encoding {
+            "gzip" => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "deflate" => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "br" => {
+                let mut compressed = Vec::new();
+                let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                writer.write_all(data).unwrap();
+                drop(writer);
+                compressed
+            }
+            "zstd" => zstd::encode_all(data, 3).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Decompresses the input fed in chunks of `chunk_len` bytes, then an empty last chunk.
+    fn decompress(
+        encoding: &str,
+        input: &[u8],
+        chunk_len: usize,
+        max_size: Option<u64>,
+        max_ratio: Option<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut decompressor =
+            Decompressor::new(encoding, max_size, max_ratio, "body".into())?.expect("not identity");
+        let mut output = Vec::new();
+        for chunk in input.chunks(chunk_len) {
+            output.extend_from_slice(&decompressor.decompress(chunk, false)?);
+        }
+        output.extend_from_slice(&decompressor.decompress(&[], true)?);
+        Ok(output)
+    }
+
+    /// Text that compresses well, but not so well that it trips the ratio limit.
+    fn text(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"lorem ipsum dolor sit amet "[(state >> 16) as usize % 27]
+            })
+            .collect()
+    }
+
+    const ENCODINGS: [&str; 4] = ["gzip", "deflate", "br", "zstd"];
+
+    #[test]
+    fn every_codec_in_one_byte_chunks() {
+        let data = text(100_000);
+        for encoding in ENCODINGS {
+            let compressed = compress(encoding, &data);
+            for chunk_len in [1, 7, compressed.len()] {
+                let output = decompress(encoding, &compressed, chunk_len, None, Some(100));
+                assert!(
+                    output.unwrap() == data,
+                    "{} in chunks of {}",
+                    encoding,
+                    chunk_len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn codings() {
+        for encoding in ["", "identity", " Identity "] {
+            assert!(Decompressor::new(encoding, None, None, "body".into())
+                .unwrap()
+                .is_none());
+        }
+        let err = Decompressor::new("compress", None, None, "body".into()).err();
+        assert_eq!(
+            err.map(|err| err.code()),
+            Some(MultipartError::InvalidContentEncoding)
+        );
+
+        assert!(is_supported("GZIP"));
+        assert!(is_supported(" x-gzip"));
+        assert!(!is_supported("identity"));
+        assert!(!is_supported("compress"));
+        assert!(!is_supported("x-vendor"));
+    }
+
+    #[test]
+    fn size_limit() {
+        let data = text(100_000);
+        for encoding in ENCODINGS {
+            let compressed = compress(encoding, &data);
+            let len = data.len() as u64;
+            assert!(decompress(encoding, &compressed, 4096, Some(len), None).is_ok());
+            let err = decompress(encoding, &compressed, 4096, Some(len - 1), None).unwrap_err();
+            assert_eq!(
+                err.code(),
+                MultipartError::SizeLimitExceeded,
+                "{}",
+                encoding
+            );
+        }
+
+        // The limit is checked after every block, however large the chunk.
+        let bomb = compress("gzip", &vec![0; 8 << 20]);
+        let err = decompress("gzip", &bomb, bomb.len(), Some(1 << 20), None).unwrap_err();
+        assert_eq!(err.code(), MultipartError::SizeLimitExceeded);
+        assert!(err.message().contains("1048576 bytes"));
+    }
+
+    #[test]
+    fn ratio_limit() {
+        let zeros = vec![0; 2 << 20];
+        for encoding in ENCODINGS {
+            let compressed = compress(encoding, &zeros);
+            let err = decompress(encoding, &compressed, 4096, None, Some(100)).unwrap_err();
+            assert_eq!(
+                err.code(),
+                MultipartError::SizeLimitExceeded,
+                "{}",
+                encoding
+            );
+            assert!(decompress(encoding, &compressed, 4096, None, None).is_ok());
+        }
+
+        // Small content is not held to the ratio.
+        let compressed = compress("gzip", &[b' '; RATIO_GRACE as usize]);
+        assert!(decompress("gzip", &compressed, 4096, None, Some(1)).is_ok());
+        let compressed = compress("gzip", &[b' '; RATIO_GRACE as usize + 1]);
+        assert!(decompress("gzip", &compressed, 4096, None, Some(1)).is_err());
+    }
+
+    #[test]
+    fn truncated_content() {
+        let data = text(10_000);
+        for encoding in ENCODINGS {
+            let compressed = compress(encoding, &data);
+            let truncated = &compressed[..compressed.len() - 5];
+            let err = decompress(encoding, truncated, 64, None, None).unwrap_err();
+            assert_eq!(
+                err.code(),
+                MultipartError::InvalidContentEncoding,
+                "{}",
+                encoding
+            );
+
+            let err = decompress(encoding, &[], 64, None, None).unwrap_err();
+            assert_eq!(
+                err.code(),
+                MultipartError::InvalidContentEncoding,
+                "{}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn data_after_the_end() {
+        let data = text(10_000);
+        for encoding in ENCODINGS {
+            let mut compressed = compress(encoding, &data);
+            compressed.extend_from_slice(b"trailing junk");
+            for chunk_len in [1, compressed.len()] {
+                let err = decompress(encoding, &compressed, chunk_len, None, None).unwrap_err();
+                assert_eq!(
+                    err.code(),
+                    MultipartError::InvalidContentEncoding,
+                    "{}",
+                    encoding
+                );
+            }
+        }
+
+        // Concatenated zstd frames are one content.
+        let mut frames = compress("zstd", b"one ");
+        frames.extend(compress("zstd", b"two"));
+        assert_eq!(
+            decompress("zstd", &frames, 3, None, None).unwrap(),
+            b"one two"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::sanitize::sanitize_filename;
+use encoding_rs::{Encoding, UTF_8};
+use percent_encoding::percent_decode;
+use std::borrow::Cow;
+
+/// The filename of a part, taken from its Content-Disposition header.
+#[derive(Debug, Default)]
+pub(crate) struct FileName {
+    /// The filename decoded to UTF-8.
+    pub decoded: String,
+    /// The parameter value as it was sent, e.g. `UTF-8''%E6%97%A5%E6%9C%AC.pdf`.
+    pub raw: String,
+    /// Whether `decoded` was changed by `sanitize`.
+    pub altered: bool,
+}
+
+impl FileName {
+    /// Replaces the decoded filename by its sanitised form, recording whether it changed.
+    pub fn sanitize(&mut self) {
+        if let Cow::Owned(sanitized) = sanitize_filename(&self.decoded) {
+            self.decoded = sanitized;
+            self.altered = true;
+        }
+    }
+}
+
+/// Returns the filename from a Content-Disposition header value, or `None` if it has none.
+///
+/// The extended `filename*` parameter of RFC 5987 is preferred, then its RFC 2231
+/// continuations (`filename*0*`, `filename*1`, ...), then the plain `filename`, and then a
+/// `filename*` that is not in the extended form.
+/// Extended values are percent-decoded and transcoded from their charset; plain values
+/// that are not valid UTF-8 have the malformed sequences replaced by U+FFFD.
+pub(crate) fn file_name(header: &[u8]) -> Option<FileName> {
+    let params = parameters(header);
+    let find = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_slice())
+    };
+
+    if let Some(value) = find("filename*") {
+        if let Some(decoded) = decode_extended(value) {
+            return Some(FileName {
+                decoded,
+                raw: lossy(value),
+                altered: false,
+            });
+        }
+    }
+
+    if let Some(file_name) = continuations(&params) {
+        return Some(file_name);
+    }
+
+    // A malformed extended value still marks the part as a file, and is kept as it is.
+    find("filename")
+        .or_else(|| find("filename*"))
+        .map(|value| FileName {
+            decoded: lossy(value),
+            raw: lossy(value),
+            altered: false,
+        })
+}
+
+/// Splits the parameters of a header value into pairs of lowercase names and values,
+/// unquoting quoted values. The disposition type before the first `;` is skipped,
+/// as are parameters without a value.
+fn parameters(header: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut params = Vec::new();
+    let mut rest = match header.iter().position(|&b| b == b';') {
+        Some(pos) => &header[pos + 1..],
+        None => return params,
+    };
+
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'=' || b == b';')
+            .unwrap_or(rest.len());
+        let name = rest[..end].trim_ascii();
+        if rest.get(end) != Some(&b'=') {
+            rest = rest.get(end + 1..).unwrap_or_default();
+            continue;
+        }
+        rest = rest[end + 1..].trim_ascii_start();
+
+        let value = match rest.strip_prefix(b"\"") {
+            Some(quoted) => {
+                let (value, len) = unquote(quoted);
+                rest = &quoted[len..];
+                value
+            }
+            None => {
+                let len = rest.iter().position(|&b| b == b';').unwrap_or(rest.len());
+                let value = rest[..len].trim_ascii_end().to_vec();
+                rest = &rest[len..];
+                value
+            }
+        };
+        // Skip anything up to the next parameter.
+        let next = rest
+            .iter()
+            .position(|&b| b == b';')
+            .map_or(rest.len(), |pos| pos + 1);
+        rest = &rest[next..];
+
+        params.push((String::from_utf8_lossy(name).to_ascii_lowercase(), value));
+    }
+
+    params
+}
+
+/// Unquotes a quoted string that starts after its opening quote, returning the value and the
+/// number of bytes read, including the closing quote. Like browsers, only `\"` is an escape,
+/// so that Windows paths keep their backslashes.
+fn unquote(quoted: &[u8]) -> (Vec<u8>, usize) {
+    let mut value = Vec::new();
+    let mut i = 0;
+    while i < quoted.len() {
+        match &quoted[i..] {
+            [b'"', ..] => return (value, i + 1),
+            [b'\\', b'"', ..] => {
+                value.push(b'"');
+                i += 2;
+            }
+            [b, ..] => {
+                value.push(*b);
+                i += 1;
+            }
+            [] => break,
+        }
+    }
+    (value, i)
+}
+
+/// Decodes an RFC 5987 extended value: `charset'language'percent-encoded-value`.
+/// Returns `None` if the value is not in that form.
+fn decode_extended(value: &[u8]) -> Option<String> {
+    let mut parts = value.splitn(3, |&b| b == b'\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes: Cow<'_, [u8]> = percent_decode(encoded).into();
+    Some(decode_charset(charset, &bytes))
+}
+
+/// Joins the RFC 2231 continuations of the filename, e.g. `filename*0*=UTF-8''a%20;
+/// filename*1="b.txt"`, and decodes them. Segments are joined in order, stopping at the first
+/// missing number; only extended segments, whose names end with `*`, are percent-decoded,
+/// and only the first one carries the charset.
+fn continuations(params: &[(String, Vec<u8>)]) -> Option<FileName> {
+    let mut segments: Vec<(usize, bool, &[u8])> = params
+        .iter()
+        .filter_map(|(key, value)| {
+            let section = key.strip_prefix("filename*")?;
+            let (number, extended) = match section.strip_suffix('*') {
+                Some(number) => (number, true),
+                None => (section, false),
+            };
+            let number = number.parse().ok()?;
+            Some((number, extended, value.as_slice()))
+        })
+        .collect();
+    segments.sort_by_key(|&(number, _, _)| number);
+    if !matches!(segments.first(), Some((0, _, _))) {
+        return None;
+    }
+
+    let mut charset: &[u8] = b"";
+    let mut bytes = Vec::new();
+    let mut raw = Vec::new();
+    for (i, &(number, extended, mut value)) in segments.iter().enumerate() {
+        if number != i {
+            break;
+        }
+        raw.extend_from_slice(value);
+
+        if !extended {
+            bytes.extend_from_slice(value);
+            continue;
+        }
+        if i == 0 {
+            let mut parts = value.splitn(3, |&b| b == b'\'');
+            if let (Some(first), Some(_language), Some(encoded)) =
+                (parts.next(), parts.next(), parts.next())
+            {
+                charset = first;
+                value = encoded;
+            }
+        }
+        bytes.extend(percent_decode(value));
+    }
+
+    Some(FileName {
+        decoded: decode_charset(charset, &bytes),
+        raw: lossy(&raw),
+        altered: false,
+    })
+}
+
+/// Transcodes the bytes from the named charset to UTF-8, assuming UTF-8 if it is unknown.
+fn decode_charset(charset: &[u8], bytes: &[u8]) -> String {
+    let encoding = Encoding::for_label(charset).unwrap_or(UTF_8);
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(header: &str) -> Option<String> {
+        file_name(header.as_bytes()).map(|file_name| file_name.decoded)
+    }
+
+    #[test]
+    fn continuations_are_joined_in_numeric_order() {
+        let header = "form-data; name=\"f\"; filename*1*=%20b; filename*10=.txt; \
+                      filename*0*=UTF-8''a; filename*2=c; filename*3=d; filename*4=e; \
+                      filename*5=f; filename*6=g; filename*7=h; filename*8=i; filename*9=j";
+        assert_eq!(decoded(header).as_deref(), Some("a bcdefghij.txt"));
+    }
+
+    #[test]
+    fn continuations_stop_at_a_missing_number() {
+        let header = "form-data; filename*0=a; filename*2=c";
+        assert_eq!(decoded(header).as_deref(), Some("a"));
+
+        // Without a first segment, there is no filename to continue.
+        assert_eq!(decoded("form-data; filename*1=b"), None);
+    }
+
+    #[test]
+    fn only_extended_segments_are_percent_decoded() {
+        let header = "form-data; filename*0*=UTF-8''%E6%97%A5; filename*1=\"%41.txt\"";
+        let file_name = file_name(header.as_bytes()).unwrap();
+        assert_eq!(file_name.decoded, "日%41.txt");
+        assert_eq!(file_name.raw, "UTF-8''%E6%97%A5%41.txt");
+    }
+
+    #[test]
+    fn the_first_segment_carries_the_charset() {
+        let header = "form-data; filename*0*=ISO-8859-1''caf%E9; filename*1*=%E9.txt";
+        assert_eq!(decoded(header).as_deref(), Some("caféé.txt"));
+    }
+
+    #[test]
+    fn extended_filename_is_preferred() {
+        let header = "form-data; filename=\"plain.txt\"; filename*=UTF-8''%E2%82%AC.txt";
+        let file_name = file_name(header.as_bytes()).unwrap();
+        assert_eq!(file_name.decoded, "€.txt");
+        assert_eq!(file_name.raw, "UTF-8''%E2%82%AC.txt");
+
+        // A malformed extended value falls back to the plain filename.
+        let header = "form-data; filename*=no-quotes.txt; filename=\"plain.txt\"";
+        assert_eq!(decoded(header).as_deref(), Some("plain.txt"));
+    }
+
+    #[test]
+    fn semicolons_inside_quoted_values() {
+        let params = parameters(b"form-data; name=\"a;b\"; filename=\"x; y.txt\"; size=3");
+        assert_eq!(
+            params,
+            vec![
+                ("name".to_string(), b"a;b".to_vec()),
+                ("filename".to_string(), b"x; y.txt".to_vec()),
+                ("size".to_string(), b"3".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_strings_only_unescape_quotes() {
+        let header = r#"form-data; filename="say \"hi\" from C:\temp\a.txt""#;
+        assert_eq!(
+            decoded(header).as_deref(),
+            Some(r#"say "hi" from C:\temp\a.txt"#)
+        );
+    }
+
+    #[test]
+    fn malformed_parameters() {
+        // Names are case-insensitive, and parameters without a value are skipped.
+        let params = parameters(b"form-data; flag; NAME = f ; filename=\"open");
+        assert_eq!(
+            params,
+            vec![
+                ("name".to_string(), b"f".to_vec()),
+                ("filename".to_string(), b"open".to_vec()),
+            ]
+        );
+        assert!(parameters(b"form-data").is_empty());
+        assert_eq!(decoded("form-data; name=\"f\""), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::ffi::{CString, NulError};
+use std::fmt;
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Error codes reported to C callers through `multipart_last_error`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultipartError {
+    /// No error occurred.
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullArgument = 1,
+    /// The Content-Type is not `multipart/form-data` or could not be parsed.
+    InvalidContentType = 2,
+    /// The boundary is missing or is not valid per RFC 2046.
+    MissingBoundary = 3,
+    /// The body ended before the closing boundary.
+    IncompleteStream = 4,
+    /// A part's headers are malformed or incomplete.
+    BadHeaders = 5,
+    /// A size limit was exceeded.
+    SizeLimitExceeded = 6,
+    /// A name, filename or header is not valid UTF-8.
+    InvalidUtf8 = 7,
+    /// A part has no `name` in its Content-Disposition header.
+    MissingName = 8,
+    /// A part's name is not in the list of allowed fields.
+    UnknownField = 9,
+    /// A name, filename or value contains a NUL byte and cannot be a C string.
+    EmbeddedNul = 10,
+    /// An unexpected internal failure.
+    Internal = 11,
+    /// The body has more parts than allowed by the parse options.
+    TooManyParts = 12,
+    /// A callback returned non-zero.
+    Aborted = 13,
+    /// Reading or writing a file on disk failed.
+    Io = 14,
+    /// The detected content type of a file is not allowed for its field.
+    DisallowedContentType = 15,
+    /// The content of a part is not valid for its Content-Transfer-Encoding.
+    InvalidTransferEncoding = 16,
+    /// The body has an unsupported Content-Encoding, or the body or a part could not be
+    /// decompressed.
+    InvalidContentEncoding = 17,
+    /// No field has the name passed to a typed getter.
+    MissingField = 18,
+    /// A field's value could not be converted to the type asked for.
+    InvalidValue = 19,
+    /// The form data does not follow the schema of the parse options.
+    ValidationFailed = 20,
+    /// A rule of the schema is malformed, such as a pattern that is not a valid regular expression.
+    InvalidSchema = 21,
+    /// A parse option has a value out of its range, such as an unknown hash algorithm.
+    InvalidOption = 22,
+    /// A NUL-terminated value was asked of borrowed form data, whose values are not
+    /// NUL-terminated.
+    BorrowedValue = 23,
+}
+
+/// An error code together with a human readable message.
+#[derive(Clone, Debug)]
+pub struct Error {
+    code: MultipartError,
+    message: String,
+}
+
+impl Error {
+    pub(crate) fn new(code: MultipartError, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The error code reported to C callers for this error.
+    pub fn code(&self) -> MultipartError {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<multer::Error> for Error {
+    fn from(err: multer::Error) -> Self {
+        // A nested multipart part is read from its parent part, whose errors come wrapped.
+        let err = match err {
+            // The same goes for a body that failed to decompress.
+            multer::Error::StreamReadFailed(source) => match source.downcast::<multer::Error>() {
+                Ok(source) => return Error::from(*source),
+                Err(source) => match source.downcast::<Error>() {
+                    Ok(source) => return *source,
+                    Err(source) => multer::Error::StreamReadFailed(source),
+                },
+            },
+            err => err,
+        };
+
+        let code = match err {
+            multer::Error::UnknownField { .. } => MultipartError::UnknownField,
+            multer::Error::IncompleteFieldData { .. } | multer::Error::IncompleteStream => {
+                MultipartError::IncompleteStream
+            }
+            multer::Error::IncompleteHeaders
+            | multer::Error::ReadHeaderFailed(_)
+            | multer::Error::DecodeHeaderName { .. }
+            | multer::Error::DecodeHeaderValue { .. } => MultipartError::BadHeaders,
+            multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+                MultipartError::SizeLimitExceeded
+            }
+            multer::Error::NoMultipart | multer::Error::DecodeContentType(_) => {
+                MultipartError::InvalidContentType
+            }
+            multer::Error::NoBoundary => MultipartError::MissingBoundary,
+            multer::Error::StreamReadFailed(_) => MultipartError::Io,
+            _ => MultipartError::Internal,
+        };
+
+        Error::new(code, err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::new(MultipartError::Io, err.to_string())
+    }
+}
+
+impl From<NulError> for Error {
+    fn from(err: NulError) -> Self {
+        Error::new(MultipartError::EmbeddedNul, err.to_string())
+    }
+}
+
+thread_local! {
+    // The last error reported on this thread, kept as a C string so that
+    // `multipart_last_error_message` can hand out a stable pointer.
+    static LAST_ERROR: RefCell<Option<(MultipartError, CString)>> = const { RefCell::new(None) };
+}
+
+/// Records the error as the last error on this thread.
+pub fn set_last_error(err: Error) {
+    // Messages come from our own code and multer, but strip NULs just in case.
+    let message = CString::new(err.message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((err.code, message)));
+}
+
+/// Clears the last error on this thread.
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Runs `f` on behalf of a C caller: records its outcome as the thread's last error
+/// and makes sure a panic never unwinds across the FFI boundary.
+pub fn ffi_guard<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    clear_last_error();
+
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        Err(Error::new(
+            MultipartError::Internal,
+            "panic while parsing form data",
+        ))
+    });
+
+    if let Err(err) = &result {
+        set_last_error(err.clone());
+    }
+
+    result
+}
+
+/// Runs `f` like `ffi_guard` and returns its outcome as an error code.
+pub fn ffi_status(f: impl FnOnce() -> Result<(), Error>) -> MultipartError {
+    ffi_guard(f)
+        .err()
+        .map_or(MultipartError::Ok, |err| err.code)
+}
+
+/// Returns the error code of the last failed call on this thread,
+/// or `Ok` if the last call succeeded.
+#[no_mangle]
+pub extern "C" fn multipart_last_error() -> MultipartError {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(MultipartError::Ok, |(code, _)| *code)
+    })
+}
+
+/// Returns the message of the last failed call on this thread, or null if the last call succeeded.
+/// The string is owned by the library and stays valid until the next call into the library on this thread.
+#[no_mangle]
+pub extern "C" fn multipart_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |(_, message)| message.as_ptr())
+    })
+}
@@ -0,0 +1,756 @@
+//! Safe Rust API for parsing multipart form data into owned values.
+//!
+//! ```
+//! use multipart_rs_multer::{form, Options};
+//!
+//! let content_type = "multipart/form-data; boundary=X";
+//! let body = "--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--X--\r\n";
+//!
+//! let form = form::parse_blocking(content_type, body, &Options::default()).unwrap();
+//! assert_eq!(form.field("a").map(|field| field.value()), Some("1"));
+//! ```
+
+use crate::borrowed::SliceCollector;
+use crate::boundary;
+use crate::decompress::{self, Decompressor};
+use crate::disposition::FileName;
+use crate::error::{Error, MultipartError};
+use crate::hash::{Digest, Hasher};
+use crate::options::Options;
+use crate::schema::{self, FieldSchema, FieldType};
+use crate::sink::{FormCollector, PartMeta, PartSink};
+use crate::sniff;
+use crate::spool::{move_file, FileContent};
+use crate::transfer::Decoder;
+use crate::urlencoded;
+use encoding_rs::Encoding;
+use futures::future::Either;
+use futures::stream::{once, Stream, StreamExt};
+use mime::Mime;
+use multer::bytes::Bytes;
+use multer::Multipart;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A text field of the form.
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub(crate) name: String,
+    // Transcoded from `raw` on first use, so that borrowed parses do not copy every value.
+    pub(crate) value: OnceLock<String>,
+    pub(crate) charset: &'static Encoding,
+    pub(crate) raw: Bytes,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl Field {
+    /// Name of the field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Value of the field, transcoded to UTF-8 from the charset of its Content-Type,
+    /// or of the form's `_charset_` field if it declares none.
+    pub fn value(&self) -> &str {
+        self.value.get_or_init(|| self.decode())
+    }
+
+    /// Value of the field exactly as it was received.
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+
+    /// Every header of the part, with lowercase names.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Transcodes the raw value to UTF-8, replacing malformed sequences with U+FFFD.
+    pub(crate) fn decode(&self) -> String {
+        self.charset
+            .decode_without_bom_handling(&self.raw)
+            .0
+            .into_owned()
+    }
+
+    /// Value of the field as a decimal 64-bit signed integer, ignoring surrounding whitespace.
+    /// Fails with `InvalidValue` if it is not one.
+    pub fn as_i64(&self) -> Result<i64, Error> {
+        schema::convert(
+            &self.name,
+            self.value(),
+            FieldType::Integer,
+            schema::parse_i64,
+        )
+    }
+
+    /// Value of the field as a finite number, ignoring surrounding whitespace.
+    /// Fails with `InvalidValue` if it is not one.
+    pub fn as_f64(&self) -> Result<f64, Error> {
+        schema::convert(
+            &self.name,
+            self.value(),
+            FieldType::Float,
+            schema::parse_f64,
+        )
+    }
+
+    /// Value of the field as a boolean: `true`, `1`, `on` or `yes` and `false`, `0`, `off` or
+    /// `no`, ignoring case. Fails with `InvalidValue` for anything else.
+    pub fn as_bool(&self) -> Result<bool, Error> {
+        schema::convert(
+            &self.name,
+            self.value(),
+            FieldType::Boolean,
+            schema::parse_bool,
+        )
+    }
+
+    /// Value of the field as an RFC 3339 date or date and time, in seconds since the Unix
+    /// epoch; see `FieldType::Date`. Fails with `InvalidValue` if it is not one.
+    pub fn as_date(&self) -> Result<i64, Error> {
+        schema::convert(
+            &self.name,
+            self.value(),
+            FieldType::Date,
+            schema::parse_date,
+        )
+    }
+
+    /// Value of the field parsed as JSON. Fails with `InvalidValue` if it is not valid JSON.
+    pub fn as_json(&self) -> Result<serde_json::Value, Error> {
+        schema::convert(
+            &self.name,
+            self.value(),
+            FieldType::Json,
+            schema::parse_json,
+        )
+    }
+}
+
+/// A file uploaded with the form. Files spooled to disk are deleted when dropped,
+/// unless they are persisted first.
+#[derive(Debug)]
+pub struct File {
+    pub(crate) field_name: String,
+    pub(crate) filename: String,
+    pub(crate) raw_filename: String,
+    pub(crate) filename_altered: bool,
+    pub(crate) content_type: Mime,
+    pub(crate) detected_content_type: &'static str,
+    pub(crate) content: FileContent,
+    pub(crate) digest: Option<Digest>,
+    pub(crate) transfer_encoding: Option<String>,
+    pub(crate) content_encoding: Option<String>,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl File {
+    /// Name of the field that the file is associated with.
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    /// Filename of the file, as sent by the client. An RFC 5987 `filename*` parameter is
+    /// preferred over a plain `filename`, and is decoded from its charset.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Filename exactly as it appeared in the Content-Disposition header, before decoding,
+    /// e.g. `UTF-8''%E6%97%A5%E6%9C%AC.pdf`. Empty for files of a nested multipart part
+    /// that had no filename.
+    pub fn raw_filename(&self) -> &str {
+        &self.raw_filename
+    }
+
+    /// Whether `filename` was changed by the `sanitize_filenames` option.
+    pub fn filename_altered(&self) -> bool {
+        self.filename_altered
+    }
+
+    /// Content type of the file, `application/octet-stream` if the part had none.
+    pub fn content_type(&self) -> &Mime {
+        &self.content_type
+    }
+
+    /// Content type detected from the start of the content, whatever the declared one:
+    /// `text/plain` for unrecognised text and `application/octet-stream` for anything else
+    /// that is not recognised.
+    pub fn detected_content_type(&self) -> &str {
+        self.detected_content_type
+    }
+
+    /// Digest of the content, computed while parsing with the `hash_algorithm` option,
+    /// or `None` if files are not hashed.
+    pub fn digest(&self) -> Option<&Digest> {
+        self.digest.as_ref()
+    }
+
+    /// Content-Transfer-Encoding the file was sent with, in lowercase, or `None` if the part
+    /// had none. Base64 and quoted-printable contents are decoded unless the
+    /// `decode_transfer_encoding` option is off.
+    pub fn transfer_encoding(&self) -> Option<&str> {
+        self.transfer_encoding.as_deref()
+    }
+
+    /// Content-Encoding the file was sent with, in lowercase, or `None` if the part had none.
+    /// Compressed contents are decompressed unless the `decompress_parts` option is off, or
+    /// the coding is not one that can be, in which case they are kept as they were sent.
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.content_encoding.as_deref()
+    }
+
+    /// Content of the file, or `None` if it was spooled to disk.
+    pub fn content(&self) -> Option<&Bytes> {
+        match &self.content {
+            FileContent::Memory(content) => Some(content),
+            FileContent::Spooled { .. } => None,
+        }
+    }
+
+    /// Path of the temporary file on disk, or `None` if the file is kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.content {
+            FileContent::Memory(_) => None,
+            FileContent::Spooled { path, .. } => Some(path),
+        }
+    }
+
+    /// Length of the content in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.content {
+            FileContent::Memory(content) => content.len() as u64,
+            FileContent::Spooled { len, .. } => *len,
+        }
+    }
+
+    /// Whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every header of the part, with lowercase names.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Writes the file to `dest`, moving it into place if it was spooled to disk.
+    pub fn persist(self, dest: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let dest = dest.as_ref();
+
+        match self.content {
+            FileContent::Memory(content) => std::fs::write(dest, content)?,
+            FileContent::Spooled { mut path, .. } => {
+                move_file(&path, dest)?;
+                path.disable_cleanup(true);
+            }
+        }
+
+        Ok(dest.to_path_buf())
+    }
+}
+
+/// The fields and files of a form, in the order they appeared in the body.
+#[derive(Debug, Default)]
+pub struct Form {
+    pub(crate) fields: Vec<Field>,
+    pub(crate) files: Vec<File>,
+}
+
+impl Form {
+    /// Every text field, in the order they appeared in the body.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Every file, in the order they appeared in the body.
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+
+    /// Returns the first field with the given name.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Returns every field with the given name.
+    pub fn fields_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Field> {
+        self.fields.iter().filter(move |field| field.name == name)
+    }
+
+    /// Returns the first file uploaded with the given field name.
+    pub fn file(&self, name: &str) -> Option<&File> {
+        self.files.iter().find(|file| file.field_name == name)
+    }
+
+    /// Returns every file uploaded with the given field name.
+    pub fn files_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a File> {
+        self.files
+            .iter()
+            .filter(move |file| file.field_name == name)
+    }
+
+    /// Checks the form against every rule of `schema`, failing with `ValidationFailed` and a
+    /// message listing every violation. Forms parsed with a schema in their options have been
+    /// checked already.
+    pub fn validate(&self, schema: &[FieldSchema]) -> Result<(), Error> {
+        schema::validate(self, schema)
+    }
+
+    /// Splits the form into its fields and files, e.g. to persist the files.
+    pub fn into_parts(self) -> (Vec<Field>, Vec<File>) {
+        (self.fields, self.files)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Parses the multipart form data read from `stream`, taking the boundary from
+/// `content_type`, the raw value of the request's Content-Type header.
+pub async fn parse<S, O, E>(content_type: &str, stream: S, options: &Options) -> Result<Form, Error>
+where
+    S: Stream<Item = Result<O, E>> + Send + 'static,
+    O: Into<Bytes> + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+{
+    let boundary = boundary::parse_boundary(content_type)?;
+    parse_parts(stream, &boundary, options, FormCollector::new(options)).await
+}
+
+/// Parses a complete in-memory body like `parse`, blocking the current thread until done.
+/// Must not be called from within an async runtime; use `parse` there instead.
+pub fn parse_blocking(
+    content_type: &str,
+    body: impl Into<Bytes>,
+    options: &Options,
+) -> Result<Form, Error> {
+    let body = body.into();
+    let stream = once(async move { Result::<Bytes, Infallible>::Ok(body) });
+    crate::block_on(parse(content_type, stream, options))?
+}
+
+/// Parses a complete in-memory body like `parse_blocking`, except that field values and file
+/// contents are slices of `body` instead of copies, and so keep the whole body alive.
+/// Files are never spooled to disk, nested multipart parts are not expanded, and contents are
+/// neither decoded from their Content-Transfer-Encoding nor decompressed. A compressed body
+/// cannot be parsed this way and fails with `InvalidContentEncoding`.
+pub fn parse_borrowed(content_type: &str, body: Bytes, options: &Options) -> Result<Form, Error> {
+    let boundary = boundary::parse_boundary(content_type)?;
+    if decompress::body_decompressor(options)?.is_some() {
+        return Err(Error::new(
+            MultipartError::InvalidContentEncoding,
+            "a compressed body cannot be parsed in borrowed mode",
+        ));
+    }
+
+    // Only the top-level parts are located in the body, as they were sent.
+    let options = &Options {
+        max_nesting_depth: 0,
+        decode_transfer_encoding: false,
+        decompress_parts: false,
+        ..options.clone()
+    };
+    let sink = SliceCollector::new(body.clone(), &boundary);
+    let stream = once(async move { Result::<Bytes, Infallible>::Ok(body) });
+    crate::block_on(parse_parts(stream, &boundary, options, sink))?
+}
+
+/// Parses an `application/x-www-form-urlencoded` body into a form without files.
+/// Every pair becomes a field, in order, including duplicate names.
+pub fn parse_urlencoded(body: &[u8], options: &Options) -> Result<Form, Error> {
+    urlencoded::parse_urlencoded(body, options)
+}
+
+/// Parses a complete in-memory body, picking the parser from `content_type`:
+/// urlencoded bodies are parsed like `parse_urlencoded`, everything else like `parse_blocking`.
+pub fn parse_any_blocking(
+    content_type: &str,
+    body: impl Into<Bytes>,
+    options: &Options,
+) -> Result<Form, Error> {
+    let mime: Mime = content_type.parse().map_err(|_| {
+        Error::new(
+            MultipartError::InvalidContentType,
+            "Content-Type header could not be parsed",
+        )
+    })?;
+
+    if mime.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str() {
+        return parse_urlencoded(&body.into(), options);
+    }
+
+    parse_blocking(content_type, body, options)
+}
+
+/// Returns the name of the field, distinguishing a missing name from one that is not valid UTF-8.
+fn field_name(field: &multer::Field<'_>) -> Result<String, Error> {
+    if let Some(name) = field.name() {
+        return Ok(name.to_string());
+    }
+
+    let disposition = field.headers().get("content-disposition");
+    if disposition.is_some_and(|value| std::str::from_utf8(value.as_bytes()).is_err()) {
+        return Err(Error::new(
+            MultipartError::InvalidUtf8,
+            "Content-Disposition header is not valid UTF-8",
+        ));
+    }
+
+    Err(Error::new(
+        MultipartError::MissingName,
+        "part has no name in its Content-Disposition header",
+    ))
+}
+
+/// Fails if the part's headers are larger than allowed.
+fn check_header_size(
+    field: &multer::Field<'_>,
+    name: &str,
+    options: &Options,
+) -> Result<(), Error> {
+    let limit = match options.max_header_size {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    // Count each header as it appeared on the wire: "name: value\r\n".
+    let size: usize = field
+        .headers()
+        .iter()
+        .map(|(key, value)| key.as_str().len() + value.len() + 4)
+        .sum();
+
+    if size > limit {
+        return Err(Error::new(
+            MultipartError::SizeLimitExceeded,
+            format!(
+                "headers of field {:?} exceeded the size limit: {} bytes",
+                name, limit
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses the multipart form data from the given stream, using the given boundary,
+/// and hands each part to the sink as it is read.
+pub(crate) async fn parse_parts<S, O, E, K>(
+    stream: S,
+    boundary: &str,
+    options: &Options,
+    mut sink: K,
+) -> Result<Form, Error>
+where
+    S: Stream<Item = Result<O, E>> + Send + 'static,
+    O: Into<Bytes> + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+    K: PartSink,
+{
+    // Decompress the body before it reaches multer, so that its limits apply to the content.
+    let stream = stream.map(|chunk| chunk.map(Into::into).map_err(Into::into));
+    let stream = match decompress::body_decompressor(options)? {
+        Some(decompressor) => Either::Left(decompress::decompress_stream(stream, decompressor)),
+        None => Either::Right(stream),
+    };
+    let mut multipart = Multipart::with_constraints(stream, boundary, options.constraints());
+    let mut part_count = 0;
+
+    read_parts(&mut multipart, None, 0, options, &mut sink, &mut part_count).await?;
+    sink.finish(options)
+}
+
+/// Reads the parts of `multipart` and hands each to the sink, expanding nested multipart
+/// parts. The parts of a nested multipart are files named after their `parent` field,
+/// and `depth` is the number of nested multiparts they are in.
+async fn read_parts<K: PartSink>(
+    multipart: &mut Multipart<'static>,
+    parent: Option<&str>,
+    depth: usize,
+    options: &Options,
+    sink: &mut K,
+    part_count: &mut usize,
+) -> Result<(), Error> {
+    // Iterate over the fields, `next_field` method will return the next field if
+    // available.
+    while let Some(mut field) = multipart.next_field().await? {
+        if let Some(max) = options.max_parts.filter(|&max| *part_count >= max) {
+            return Err(Error::new(
+                MultipartError::TooManyParts,
+                format!("form data has more than {} parts", max),
+            ));
+        }
+        *part_count += 1;
+
+        let name = match parent {
+            Some(parent) => parent.to_string(),
+            None => field_name(&field)?,
+        };
+        check_header_size(&field, &name, options)?;
+
+        let mut part = PartMeta::new(name, &field);
+        if parent.is_some() {
+            part.file_name.get_or_insert_with(FileName::default);
+        }
+        if let Some(file_name) = part
+            .file_name
+            .as_mut()
+            .filter(|_| options.sanitize_filenames)
+        {
+            file_name.sanitize();
+        }
+
+        let nested = part
+            .content_type
+            .as_ref()
+            .and_then(boundary::nested_boundary)
+            .filter(|_| depth < options.max_nesting_depth);
+        if let Some(nested) = nested {
+            // The nested parts are read straight from this part's content as it streams in.
+            let mut nested = Multipart::new(field, nested);
+            let parent = Some(part.name.as_str());
+            Box::pin(read_parts(
+                &mut nested,
+                parent,
+                depth + 1,
+                options,
+                sink,
+                part_count,
+            ))
+            .await?;
+            continue;
+        }
+
+        let limit = if part.is_file() {
+            options.max_file_size
+        } else {
+            options.max_field_size
+        };
+        let name = part.name.clone();
+        let mut decoder = part
+            .transfer_encoding
+            .as_deref()
+            .filter(|_| options.decode_transfer_encoding)
+            .and_then(Decoder::new);
+        // Content sent with a coding that cannot be decompressed is kept as it was sent.
+        let mut decompressor = match part
+            .content_encoding
+            .as_deref()
+            .filter(|&encoding| options.decompress_parts && decompress::is_supported(encoding))
+        {
+            Some(encoding) => Decompressor::new(
+                encoding,
+                limit,
+                options.max_decompression_ratio,
+                format!("field {:?}", part.name),
+            )?,
+            None => None,
+        };
+        let mut hasher = Hasher::new(options.hash_algorithm).filter(|_| part.is_file());
+        let mut sniffer = Sniffer::begin(part, sink)?;
+
+        // Hand over the decoded content chunk by chunk, failing once it grows past the limit.
+        // An empty last chunk flushes whatever the decoders held back.
+        let mut size: u64 = 0;
+        let mut last = false;
+        while !last {
+            let mut chunk = match field.chunk().await? {
+                Some(chunk) => chunk,
+                None => {
+                    last = true;
+                    Bytes::new()
+                }
+            };
+            if let Some(decoder) = &mut decoder {
+                chunk = decoder.decode(&chunk, last).map_err(|err| {
+                    Error::new(
+                        MultipartError::InvalidTransferEncoding,
+                        format!("field {:?} is not valid base64: {}", name, err),
+                    )
+                })?;
+            }
+            if let Some(decompressor) = &mut decompressor {
+                chunk = decompressor.decompress(&chunk, last)?;
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+
+            size += chunk.len() as u64;
+            if let Some(limit) = limit.filter(|&limit| size > limit) {
+                return Err(Error::new(
+                    MultipartError::SizeLimitExceeded,
+                    format!("field {:?} exceeded the size limit: {} bytes", name, limit),
+                ));
+            }
+
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&chunk);
+            }
+            sniffer.data(&chunk, options, sink)?;
+        }
+
+        sniffer.end(hasher.map(Hasher::finish), options, sink)?;
+    }
+
+    Ok(())
+}
+
+/// Holds back the start of a file until its content type has been detected, so that the sink
+/// never sees a file whose content type is not allowed. Text fields go straight through.
+struct Sniffer {
+    pending: Option<PartMeta>, // The file, until its content type is known.
+    prefix: Vec<u8>,           // The content held back so far.
+}
+
+impl Sniffer {
+    fn begin<K: PartSink>(part: PartMeta, sink: &mut K) -> Result<Self, Error> {
+        let pending = if part.is_file() {
+            Some(part)
+        } else {
+            sink.begin(part)?;
+            None
+        };
+
+        Ok(Sniffer {
+            pending,
+            prefix: Vec::new(),
+        })
+    }
+
+    fn data<K: PartSink>(
+        &mut self,
+        chunk: &[u8],
+        options: &Options,
+        sink: &mut K,
+    ) -> Result<(), Error> {
+        if self.pending.is_none() {
+            return sink.data(chunk);
+        }
+
+        self.prefix.extend_from_slice(chunk);
+        if self.prefix.len() >= sniff::SNIFF_LEN {
+            self.release(options, sink)?;
+        }
+        Ok(())
+    }
+
+    fn end<K: PartSink>(
+        mut self,
+        digest: Option<Digest>,
+        options: &Options,
+        sink: &mut K,
+    ) -> Result<(), Error> {
+        self.release(options, sink)?;
+        sink.end(digest)
+    }
+
+    /// Detects the content type of the pending file, checks it against the allow-list, and
+    /// hands the file to the sink along with the content held back.
+    fn release<K: PartSink>(&mut self, options: &Options, sink: &mut K) -> Result<(), Error> {
+        let mut part = match self.pending.take() {
+            Some(part) => part,
+            None => return Ok(()),
+        };
+
+        // Browsers send an empty part without a filename when no file was chosen, which is
+        // no file to check.
+        let chosen = !self.prefix.is_empty()
+            || part
+                .file_name
+                .as_ref()
+                .is_some_and(|file_name| !file_name.raw.is_empty());
+        let detected = sniff::sniff(&self.prefix);
+        if chosen {
+            options.check_content_type(&part.name, detected)?;
+        }
+        part.detected_content_type = Some(detected);
+        sink.begin(part)?;
+
+        let prefix = std::mem::take(&mut self.prefix);
+        if !prefix.is_empty() {
+            sink.data(&prefix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine as _;
+
+    /// Parses a form with one base64-encoded part of `len` bytes, a file if `filename` is set.
+    fn parse_base64(len: usize, filename: Option<&str>, options: &Options) -> Result<Form, Error> {
+        let encoded = STANDARD.encode(vec![b'a'; len]);
+        let lines: Vec<_> = encoded.as_bytes().chunks(76).collect();
+        let disposition = match filename {
+            Some(filename) => format!("name=\"p\"; filename=\"{}\"", filename),
+            None => "name=\"p\"".to_string(),
+        };
+        let body = format!(
+            "--X\r\nContent-Disposition: form-data; {}\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--X--\r\n",
+            disposition,
+            String::from_utf8(lines.join(&b"\r\n"[..])).unwrap()
+        );
+        parse_blocking("multipart/form-data; boundary=X", body, options)
+    }
+
+    #[test]
+    fn size_limits_apply_to_decoded_content() {
+        let options = Options {
+            max_file_size: Some(3000),
+            max_field_size: Some(100),
+            ..Options::default()
+        };
+
+        // The encoded file is about 4100 bytes long, more than either limit.
+        let form = parse_base64(3000, Some("a.txt"), &options).unwrap();
+        assert_eq!(form.files()[0].len(), 3000);
+        let err = parse_base64(3001, Some("a.txt"), &options).unwrap_err();
+        assert_eq!(err.code(), MultipartError::SizeLimitExceeded);
+
+        let form = parse_base64(100, None, &options).unwrap();
+        assert_eq!(form.fields()[0].value().len(), 100);
+        let err = parse_base64(101, None, &options).unwrap_err();
+        assert_eq!(err.code(), MultipartError::SizeLimitExceeded);
+
+        // Setting the other limit makes no difference.
+        let only_files = Options {
+            max_field_size: None,
+            ..options
+        };
+        assert!(parse_base64(3000, Some("a.txt"), &only_files).is_ok());
+    }
+
+    #[test]
+    fn unsupported_part_codings_are_kept() {
+        let body = "--X\r\nContent-Disposition: form-data; name=\"p\"; filename=\"a.Z\"\r\n\
+                    Content-Encoding: Compress\r\n\r\nsent as is\r\n--X--\r\n";
+        let form =
+            parse_blocking("multipart/form-data; boundary=X", body, &Options::default()).unwrap();
+        let file = &form.files()[0];
+        assert_eq!(file.content_encoding(), Some("compress"));
+        assert_eq!(file.header("content-encoding"), Some("Compress"));
+        assert_eq!(file.content().unwrap().as_ref(), b"sent as is");
+    }
+}
@@ -0,0 +1,118 @@
+use crate::error::{Error, MultipartError};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest as _, Sha256};
+
+/// Algorithm used to hash the content of each file while it is parsed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Files are not hashed.
+    #[default]
+    None = 0,
+    /// SHA-256, a 32-byte digest.
+    Sha256 = 1,
+    /// SHA-1, a 20-byte digest. Only suitable for compatibility with existing systems.
+    Sha1 = 2,
+    /// MD5, a 16-byte digest. Only suitable for compatibility with existing systems.
+    Md5 = 3,
+    /// CRC-32C (Castagnoli), a 4-byte big-endian checksum. Detects corruption only.
+    Crc32c = 4,
+    /// BLAKE3, a 32-byte digest.
+    Blake3 = 5,
+}
+
+impl TryFrom<u32> for HashAlgorithm {
+    type Error = Error;
+
+    /// Converts the value of a `HashAlgorithm` passed from C, failing with `InvalidOption`
+    /// if it is not one of the algorithms.
+    fn try_from(value: u32) -> Result<Self, Error> {
+        Ok(match value {
+            0 => HashAlgorithm::None,
+            1 => HashAlgorithm::Sha256,
+            2 => HashAlgorithm::Sha1,
+            3 => HashAlgorithm::Md5,
+            4 => HashAlgorithm::Crc32c,
+            5 => HashAlgorithm::Blake3,
+            _ => {
+                return Err(Error::new(
+                    MultipartError::InvalidOption,
+                    format!("hash algorithm {} is not valid", value),
+                ))
+            }
+        })
+    }
+}
+
+/// The digest of a file's content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Digest {
+    algorithm: HashAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    /// Algorithm that computed the digest.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// The digest as raw bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The digest as lowercase hexadecimal.
+    pub fn hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Hashes a file's content chunk by chunk with the chosen algorithm.
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+    Md5(Md5),
+    Crc32c(u32),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    /// Returns a hasher for the algorithm, or `None` if files are not hashed.
+    pub fn new(algorithm: HashAlgorithm) -> Option<Self> {
+        Some(match algorithm {
+            HashAlgorithm::None => return None,
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            HashAlgorithm::Crc32c => Hasher::Crc32c(0),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Sha1(hasher) => hasher.update(chunk),
+            Hasher::Md5(hasher) => hasher.update(chunk),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, chunk),
+            Hasher::Blake3(hasher) => {
+                hasher.update(chunk);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Digest {
+        let (algorithm, bytes) = match self {
+            Hasher::Sha256(hasher) => (HashAlgorithm::Sha256, hasher.finalize().to_vec()),
+            Hasher::Sha1(hasher) => (HashAlgorithm::Sha1, hasher.finalize().to_vec()),
+            Hasher::Md5(hasher) => (HashAlgorithm::Md5, hasher.finalize().to_vec()),
+            Hasher::Crc32c(crc) => (HashAlgorithm::Crc32c, crc.to_be_bytes().to_vec()),
+            Hasher::Blake3(hasher) => {
+                (HashAlgorithm::Blake3, hasher.finalize().as_bytes().to_vec())
+            }
+        };
+        Digest { algorithm, bytes }
+    }
+}
@@ -1,15 +1,55 @@
-use futures::stream::once;
-use mime::Mime;
+mod arena;
+mod borrowed;
+mod boundary;
+mod callbacks;
+mod decompress;
+mod disposition;
+mod error;
+pub mod form;
+mod hash;
+mod lookup;
+mod options;
+mod parser;
+mod runtime;
+mod sanitize;
+mod schema;
+mod sink;
+mod sniff;
+mod spool;
+mod transfer;
+mod urlencoded;
+mod writer;
+
+use arena::Arena;
+use error::ffi_guard;
+use futures::stream::{once, Stream};
+use lookup::FormIndex;
 use multer::bytes::Bytes;
-use multer::Multipart;
-use once_cell::sync::Lazy;
+use runtime::block_on;
+use sink::FormCollector;
+use spool::FileContent;
 use std::convert::Infallible;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::sync::Mutex;
-use tokio::runtime;
+
+pub use callbacks::{MultipartCallbacks, PartInfo};
+pub use error::{Error, MultipartError};
+pub use form::{Field, File, Form};
+pub use hash::{Digest, HashAlgorithm};
+pub use options::{ContentTypeRule, FieldRule, Options, ParseOptions};
+pub use parser::MultipartParser;
+pub use runtime::shutdown_runtime;
+pub use sanitize::sanitize_filename;
+pub use schema::{FieldSchema, FieldType};
+pub use writer::{Chunks, MultipartWriter};
+
+/// The `mime` crate, whose `Mime` type content types are given and returned as.
+pub use mime;
 
 /// Represents a form data with fields and files.
+/// The field values of borrowed form data are not NUL-terminated, so `form_data_get_field`
+/// and `form_data_get_fields_all` fail on it with `BorrowedValue`; use `form_data_get_field_n`
+/// and `form_data_get_fields_all_n`, or each field's `value_len`, instead.
 #[repr(C)]
 #[derive(Debug)]
 pub struct FormData {
@@ -17,256 +57,509 @@ pub struct FormData {
     field_count: usize,        // Number of fields in the form data.
     files: *mut MultipartFile, // Array of files in the form data.
     file_count: usize,         // Number of files in the form data.
+    index: *mut FormIndex,     // Name index used by the `form_data_get_*` lookups.
+    borrowed: bool,            // Whether values and contents point into the caller's body.
+    memory_usage: usize,       // Size of the single allocation holding the form data.
 }
 
 /// Represents a file with filename, content type, content, and content length.
+/// Files spooled to disk have a `path` and a null `content`.
 #[repr(C)]
 #[derive(Clone, Debug)]
 pub struct MultipartFile {
     filename: *const c_char,     // Filename of the file.
     content_type: *const c_char, // Content type of the file.
-    content: *mut u8,            // Raw bytes of the file content.
+    content: *mut u8,            // Raw bytes of the file content, or null if spooled to disk.
     content_length: usize,       // Length of the file content in bytes.
     field_name: *const c_char,   // Name of the field that the file is associated with.
+    path: *const c_char,         // Path of the file on disk, or null if kept in memory.
+    is_temporary: bool,          // Whether `path` is deleted by `free_multipart_form_data`.
+    headers: *mut PartHeader,    // Array of every header of the part.
+    header_count: usize,         // Number of headers in the array.
+    raw_filename: *const c_char, // Filename as it appeared in the Content-Disposition header.
+    filename_altered: bool, // Whether `filename` was changed by the `sanitize_filenames` option.
+    // Content type detected from the start of the content, whatever the declared one.
+    detected_content_type: *const c_char,
+    digest: *const u8,         // Digest of the content, or null if not hashed.
+    digest_len: usize,         // Length of `digest` in bytes.
+    digest_hex: *const c_char, // Digest in lowercase hex, or null if not hashed.
+    // Content-Transfer-Encoding the file was sent with, in lowercase, or null if it had none.
+    transfer_encoding: *const c_char,
+    // Content-Encoding the file was sent with, in lowercase, or null if it had none.
+    content_encoding: *const c_char,
 }
 
 /// Represents a field with name and value.
+/// `value` is NUL-terminated but may contain NUL bytes itself; use `value_len` for its full length.
 #[repr(C)]
 #[derive(Clone, Debug)]
 pub struct FormField {
-    name: *const c_char,
-    value: *const c_char,
+    name: *const c_char,      // Name of the field.
+    value: *const c_char, // Value transcoded to UTF-8, with malformed sequences replaced by U+FFFD.
+    value_len: usize,     // Length of `value` in bytes, excluding the NUL terminator.
+    raw_value: *const u8, // Value exactly as it was received, before transcoding.
+    raw_value_len: usize, // Length of `raw_value` in bytes.
+    headers: *mut PartHeader, // Array of every header of the part.
+    header_count: usize,  // Number of headers in the array.
 }
 
-struct RuntimeManager {
-    runtime: Option<runtime::Runtime>,
+/// A header of a part, as a name/value pair.
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct PartHeader {
+    name: *const c_char,  // Lowercase header name.
+    value: *const c_char, // Header value, with invalid UTF-8 replaced by U+FFFD.
 }
 
-impl RuntimeManager {
-    fn new() -> Self {
-        RuntimeManager { runtime: None }
-    }
-
-    fn init(&mut self) {
-        if self.runtime.is_none() {
-            self.runtime = Some(
-                runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .expect("Failed to create Tokio runtime"),
-            );
-        }
-    }
-
-    fn runtime(&self) -> &runtime::Runtime {
-        self.runtime.as_ref().expect("Runtime not initialized")
-    }
+/// A field whose strings have been checked for NUL bytes, ready to be laid out.
+struct CField {
+    name: CString,
+    value: String,
+    raw: Bytes,
+    headers: Vec<(CString, CString)>,
+}
 
-    fn shutdown(&mut self) {
-        if let Some(runtime) = self.runtime.take() {
-            runtime.shutdown_timeout(std::time::Duration::from_secs(5));
-        }
-    }
+/// A file whose strings have been checked for NUL bytes, ready to be laid out.
+struct CFile {
+    field_name: CString,
+    filename: CString,
+    raw_filename: CString,
+    content_type: CString,
+    detected_content_type: &'static str,
+    content: FileContent,
+    digest: Option<Digest>,
+    transfer_encoding: Option<CString>,
+    content_encoding: Option<CString>,
+    filename_altered: bool,
+    path: Option<CString>,
+    headers: Vec<(CString, CString)>,
 }
 
-// Lazy static Tokio runtime manager
-static RUNTIME_MANAGER: Lazy<Mutex<RuntimeManager>> = Lazy::new(|| {
-    let mut manager = RuntimeManager::new();
-    manager.init(); // Initialize the Tokio runtime
-    Mutex::new(manager)
-});
-
-impl Drop for RuntimeManager {
-    fn drop(&mut self) {
-        if let Some(runtime) = self.runtime.take() {
-            runtime.shutdown_timeout(std::time::Duration::from_secs(5));
+impl FormData {
+    /// Converts the parsed fields and files into the raw C representation, placed in a single
+    /// allocation that starts with the returned form data.
+    /// Fails if a name, filename or value contains a NUL byte.
+    /// When `borrowed`, the field values and file contents must be slices of a body owned by
+    /// the caller; they are pointed to instead of being copied.
+    fn from_form(form: Form, borrowed: bool) -> Result<*mut FormData, Error> {
+        // Convert every string up front so that nothing has been handed over to C on failure.
+        let fields = form
+            .fields
+            .into_iter()
+            .map(|mut field| {
+                // Borrowed values are pointed to as received, so they are never transcoded.
+                let value = if borrowed {
+                    String::new()
+                } else {
+                    field.value.take().unwrap_or_else(|| field.decode())
+                };
+                Ok(CField {
+                    name: CString::new(field.name)?,
+                    value,
+                    raw: field.raw,
+                    headers: c_headers(field.headers)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut files = form
+            .files
+            .into_iter()
+            .map(|file| {
+                let path = match &file.content {
+                    FileContent::Memory(_) => None,
+                    FileContent::Spooled { path, .. } => {
+                        Some(CString::new(path.as_os_str().as_encoded_bytes())?)
+                    }
+                };
+
+                Ok(CFile {
+                    field_name: CString::new(file.field_name)?,
+                    filename: CString::new(file.filename)?,
+                    raw_filename: CString::new(file.raw_filename)?,
+                    content_type: CString::new(file.content_type.to_string())?,
+                    detected_content_type: file.detected_content_type,
+                    content: file.content,
+                    digest: file.digest,
+                    transfer_encoding: file.transfer_encoding.map(CString::new).transpose()?,
+                    content_encoding: file.content_encoding.map(CString::new).transpose()?,
+                    filename_altered: file.filename_altered,
+                    path,
+                    headers: c_headers(file.headers)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut arena = Arena::measure();
+        lay_out(&mut arena, &fields, &files, borrowed);
+        let mut arena = Arena::allocate(&arena)?;
+        let form_data = lay_out(&mut arena, &fields, &files, borrowed);
+        debug_assert!(arena.is_full());
+
+        // From here on the spooled files are deleted by free_multipart_form_data instead.
+        for file in &mut files {
+            if let FileContent::Spooled { path, .. } = &mut file.content {
+                path.disable_cleanup(true);
+            }
         }
-        println!("Dropped RuntimeManager");
+
+        Ok(form_data)
     }
 }
 
-fn rt() -> std::sync::MutexGuard<'static, RuntimeManager> {
-    RUNTIME_MANAGER.lock().unwrap()
+/// Converts the headers of a part to C strings, failing if any contains a NUL byte.
+fn c_headers(headers: Vec<(String, String)>) -> Result<Vec<(CString, CString)>, Error> {
+    headers
+        .into_iter()
+        .map(|(name, value)| Ok((CString::new(name)?, CString::new(value)?)))
+        .collect()
 }
 
-/// Parses the multipart form data from the given body.
-/// Returns a pointer to the parsed form data. If the body is null, returns a pointer to an empty form data.
-/// Likewise if the boundary is not found, returns a pointer to an empty form data that must be freed.
-/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
-// #[no_mangle]
-async fn rt_parse_multipart_form_data(body: *const c_char) -> *mut FormData {
-    let default_form_data = FormData {
-        fields: std::ptr::null_mut(),
-        field_count: 0,
-        files: std::ptr::null_mut(),
-        file_count: 0,
-    };
-
-    if body.is_null() {
-        return Box::into_raw(Box::new(default_form_data));
-    }
-
-    // Convert body to bytes as this C string may not contain valid UTF-8.
-    let body = unsafe { CStr::from_ptr(body).to_bytes() };
-
-    // Extract the boundary, find the first occurrence of '\r\n' in the body.
-    let boundary_index = body.iter().position(|&b| b == b'\r').map(|index| index + 2);
-    if boundary_index.is_none() {
-        return Box::into_raw(Box::new(default_form_data));
-    }
-
-    // Convert the boundary index to a string slice.
-    // We subtract 2 from the boundary index to exclude the '\r\n' characters.
-    // We start from 2 to exclude the leading '--' characters.
-    let boundary = std::str::from_utf8(&body[2..boundary_index.unwrap() - 2]).unwrap_or_default();
-
-    if boundary.is_empty() {
-        return Box::into_raw(Box::new(default_form_data));
-    }
-
-    // Initialize vectors to store form fields and files.
-    let mut fields: Vec<FormField> = Vec::new();
-    let mut files: Vec<MultipartFile> = Vec::new();
-
-    let stream = once(async move { Result::<Bytes, Infallible>::Ok(Bytes::from(body)) });
-
-    let mut multipart = Multipart::new(stream, boundary);
-    let default_content_type = "application/octet-stream".parse::<Mime>().unwrap();
-
-    // Iterate over the fields, `next_field` method will return the next field if
-    // available.
-    while let Some(mut field) = multipart.next_field().await.unwrap() {
-        let name = String::from(field.name().unwrap());
-        let is_file = field.file_name().is_some();
-
-        let file_name = {
-            if is_file {
-                field.file_name().unwrap().to_string()
+/// Places the form data, its arrays and everything they point to in the arena, and returns
+/// the form data, which comes first.
+fn lay_out(arena: &mut Arena, fields: &[CField], files: &[CFile], borrowed: bool) -> *mut FormData {
+    let form_data = arena.reserve::<FormData>(1);
+
+    let c_fields: Vec<FormField> = fields
+        .iter()
+        .map(|field| {
+            let (value, value_len, raw_value) = if borrowed {
+                let raw = field.raw.as_ptr();
+                (raw as *const c_char, field.raw.len(), raw)
             } else {
-                String::new()
+                let value = arena.alloc_c_buffer(field.value.as_bytes());
+                // Most values are valid UTF-8 already, so the raw value shares the copy.
+                let raw_value = if field.raw == field.value.as_bytes() {
+                    value as *const u8
+                } else {
+                    arena.alloc_bytes(&field.raw)
+                };
+                (value, field.value.len(), raw_value)
+            };
+            let (headers, header_count) = lay_out_headers(arena, &field.headers);
+
+            FormField {
+                name: arena.alloc_c_str(&field.name),
+                value,
+                value_len,
+                raw_value,
+                raw_value_len: field.raw.len(),
+                headers,
+                header_count,
             }
-        };
-
-        let content_type = field.content_type().unwrap_or(&default_content_type);
-
-        if is_file {
-            // Extract the file name, content type, and content length.
-            let content_type = content_type.to_string();
+        })
+        .collect();
+
+    let c_files: Vec<MultipartFile> = files
+        .iter()
+        .map(|file| {
+            let (content, content_length) = match &file.content {
+                FileContent::Memory(content) if borrowed => {
+                    (content.as_ptr() as *mut u8, content.len())
+                }
+                FileContent::Memory(content) => (arena.alloc_bytes(content), content.len()),
+                FileContent::Spooled { len, .. } => (std::ptr::null_mut(), *len as usize),
+            };
+            let path = file
+                .path
+                .as_deref()
+                .map_or(std::ptr::null(), |path| arena.alloc_c_str(path));
+            let (headers, header_count) = lay_out_headers(arena, &file.headers);
+            let transfer_encoding = file
+                .transfer_encoding
+                .as_deref()
+                .map_or(std::ptr::null(), |encoding| arena.alloc_c_str(encoding));
+            let content_encoding = file
+                .content_encoding
+                .as_deref()
+                .map_or(std::ptr::null(), |encoding| arena.alloc_c_str(encoding));
+            let (digest, digest_len, digest_hex) = match &file.digest {
+                Some(digest) => (
+                    arena.alloc_bytes(digest.bytes()) as *const u8,
+                    digest.bytes().len(),
+                    arena.alloc_c_buffer(digest.hex().as_bytes()),
+                ),
+                None => (std::ptr::null(), 0, std::ptr::null()),
+            };
 
-            // Read the file content into a vector.
-            let mut content: Vec<u8> = Vec::new();
-            while let Some(chunk) = field.chunk().await.unwrap() {
-                content.extend_from_slice(&chunk);
+            MultipartFile {
+                filename: arena.alloc_c_str(&file.filename),
+                raw_filename: arena.alloc_c_str(&file.raw_filename),
+                filename_altered: file.filename_altered,
+                content_type: arena.alloc_c_str(&file.content_type),
+                detected_content_type: arena.alloc_c_buffer(file.detected_content_type.as_bytes()),
+                content,
+                content_length,
+                field_name: arena.alloc_c_str(&file.field_name),
+                path,
+                is_temporary: file.path.is_some(),
+                headers,
+                header_count,
+                digest,
+                digest_len,
+                digest_hex,
+                transfer_encoding,
+                content_encoding,
             }
+        })
+        .collect();
+
+    let index = lookup::lay_out_index(
+        arena,
+        c_fields
+            .iter()
+            .zip(fields)
+            .map(|(c_field, field)| (c_field.name, field.name.as_bytes())),
+        c_files
+            .iter()
+            .zip(files)
+            .map(|(c_file, file)| (c_file.field_name, file.field_name.as_bytes())),
+    );
+
+    let form_data_value = FormData {
+        field_count: c_fields.len(),
+        fields: arena.alloc_slice(c_fields),
+        file_count: c_files.len(),
+        files: arena.alloc_slice(c_files),
+        index,
+        borrowed,
+        memory_usage: arena.size(),
+    };
+    arena.write(form_data, form_data_value);
+    form_data
+}
 
-            // Update the content length.
-            let content_length = content.len();
+/// Places the headers of a part in the arena as an array, returning it with its length.
+fn lay_out_headers(arena: &mut Arena, headers: &[(CString, CString)]) -> (*mut PartHeader, usize) {
+    let headers: Vec<PartHeader> = headers
+        .iter()
+        .map(|(name, value)| PartHeader {
+            name: arena.alloc_c_str(name),
+            value: arena.alloc_c_str(value),
+        })
+        .collect();
+    let header_count = headers.len();
+    (arena.alloc_slice(headers), header_count)
+}
 
-            // Convert the file name, content type, and field name to C strings.
-            let content_type = CString::new(content_type).unwrap().into_raw();
-            let field_name = CString::new(name).unwrap().into_raw();
-            let filename = CString::new(file_name).unwrap().into_raw();
+/// Runs a parse on behalf of a C caller, returning null on failure.
+fn ffi_parse(parse: impl FnOnce() -> Result<Form, Error>) -> *mut FormData {
+    ffi_guard(|| parse().and_then(|form| FormData::from_form(form, false)))
+        .unwrap_or(std::ptr::null_mut())
+}
 
-            // Create a new file and push it to the files vector.
-            let file = MultipartFile {
-                filename,
-                content_type,
-                content: content.as_mut_ptr(),
-                content_length,
-                field_name,
-            };
+/// Wraps a complete in-memory body in a single-item stream.
+fn body_stream(body: &'static [u8]) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    once(async move { Result::<Bytes, Infallible>::Ok(Bytes::from(body)) })
+}
 
-            files.push(file);
+/// Parses the multipart form data from a NUL-terminated body.
+/// The body is truncated at the first NUL byte, so binary uploads should use
+/// `parse_multipart_form_data_bytes` instead.
+/// Returns null on failure; see `multipart_last_error` for the reason.
+///
+/// # Safety
+/// `body` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data(body: *const c_char) -> *mut FormData {
+    let len = if body.is_null() {
+        0
+    } else {
+        CStr::from_ptr(body).to_bytes().len()
+    };
 
-            // Prevent the vector from being deallocated.
-            std::mem::forget(content);
-        } else {
-            // Extract the field value.
-            let value = field.text().await.unwrap_or_default();
+    parse_multipart_form_data_bytes(body as *const u8, len)
+}
 
-            // Convert the field name and value to C strings.
-            let name = CString::new(name).unwrap().into_raw();
-            let value = CString::new(value).unwrap().into_raw();
+/// Parses the multipart form data from the `len` bytes starting at `body`.
+/// Unlike `parse_multipart_form_data`, the body may contain NUL bytes.
+/// The boundary is guessed from the first delimiter line of the body; prefer
+/// `parse_multipart_form_data_with_content_type` when the Content-Type header is known.
+/// Returns null on failure; see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
+///
+/// # Safety
+/// `body` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data_bytes(
+    body: *const u8,
+    len: usize,
+) -> *mut FormData {
+    ffi_parse(|| {
+        let body = body_slice(body, len)?;
+        let boundary = boundary::sniff_boundary(body).ok_or_else(|| {
+            Error::new(
+                MultipartError::MissingBoundary,
+                "no valid boundary found on the first delimiter line",
+            )
+        })?;
+
+        block_on(form::parse_parts(
+            body_stream(body),
+            boundary,
+            &Options::default(),
+            FormCollector::new(&Options::default()),
+        ))?
+    })
+}
 
-            // Create a new field and push it to the fields vector.
-            let field = FormField { name, value };
-            fields.push(field);
-        }
-    }
+/// Parses the multipart form data from the `len` bytes starting at `body`, taking the
+/// boundary from `content_type`, the raw value of the request's Content-Type header.
+/// Returns null on failure, including when the header is not `multipart/form-data` or its
+/// boundary is missing or invalid per RFC 2046; see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+/// `body` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data_with_content_type(
+    content_type: *const c_char,
+    body: *const u8,
+    len: usize,
+) -> *mut FormData {
+    ffi_parse(|| {
+        let content_type = content_type_str(content_type)?;
+        let body = body_slice(body, len)?;
+
+        form::parse_blocking(content_type, body, &Options::default())
+    })
+}
 
-    // Convert vectors into boxed slices and get raw pointers.
-    let fields_slice = fields.into_boxed_slice();
-    let files_slice = files.into_boxed_slice();
+/// Parses the multipart form data like `parse_multipart_form_data_with_content_type`,
+/// applying `options`. A null `options` applies no limits and keeps every file in memory.
+/// When a size limit is exceeded the call fails with `SizeLimitExceeded`, and with
+/// `TooManyParts` when the body has more parts than allowed, and with `ValidationFailed` when
+/// the fields do not follow the schema given by `field_rules`.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+/// `body` must be null or point to at least `len` readable bytes.
+/// `options` must be null or point to a valid `ParseOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn parse_multipart_form_data_with_options(
+    content_type: *const c_char,
+    body: *const u8,
+    len: usize,
+    options: *const ParseOptions,
+) -> *mut FormData {
+    ffi_parse(|| {
+        let content_type = content_type_str(content_type)?;
+        let body = body_slice(body, len)?;
+        let options = Options::from_ffi(options)?;
+
+        form::parse_blocking(content_type, body, &options)
+    })
+}
 
-    let form_data = FormData {
-        fields: fields_slice.as_ptr() as *mut FormField,
-        field_count: fields_slice.len() as usize,
-        files: files_slice.as_ptr() as *mut MultipartFile,
-        file_count: files_slice.len(),
-    };
+/// Parses the `len` bytes starting at `body` as either multipart or urlencoded form data,
+/// picking the parser from `content_type`, the raw value of the request's Content-Type header.
+/// A null `options` applies no limits and keeps every file in memory.
+/// Returns null on failure, including when the Content-Type is neither `multipart/form-data`
+/// nor `application/x-www-form-urlencoded`; see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+/// `body` must be null or point to at least `len` readable bytes.
+/// `options` must be null or point to a valid `ParseOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn parse_form_data(
+    content_type: *const c_char,
+    body: *const u8,
+    len: usize,
+    options: *const ParseOptions,
+) -> *mut FormData {
+    ffi_parse(|| {
+        let content_type = content_type_str(content_type)?;
+        let body = body_slice(body, len)?;
+        let options = Options::from_ffi(options)?;
+
+        form::parse_any_blocking(content_type, body, &options)
+    })
+}
 
-    // Prevent the boxed slices from being deallocated.
-    std::mem::forget(fields_slice);
-    std::mem::forget(files_slice);
+/// Borrows a Content-Type header value passed from C.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string
+/// that outlives the returned string.
+unsafe fn content_type_str<'a>(content_type: *const c_char) -> Result<&'a str, Error> {
+    if content_type.is_null() {
+        return Err(Error::new(
+            MultipartError::NullArgument,
+            "content type is null",
+        ));
+    }
 
-    Box::into_raw(Box::new(form_data))
+    CStr::from_ptr(content_type).to_str().map_err(|_| {
+        Error::new(
+            MultipartError::InvalidUtf8,
+            "Content-Type header is not valid UTF-8",
+        )
+    })
 }
 
-#[no_mangle]
-pub extern "C" fn parse_multipart_form_data(body: *const c_char) -> *mut FormData {
-    // Get the Tokio runtime manager
-    let runtime = rt();
-    runtime
-        .runtime()
-        .block_on(async { rt_parse_multipart_form_data(body).await })
+/// Extracts the boundary from a Content-Type header value passed from C.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+unsafe fn content_type_boundary(content_type: *const c_char) -> Result<String, Error> {
+    boundary::parse_boundary(content_type_str(content_type)?)
 }
 
-/// Frees the given form data. If the form data is null, does nothing.
-#[no_mangle]
-pub extern "C" fn free_multipart_form_data(form_data: *mut FormData) {
-    if form_data.is_null() {
-        return;
+/// Borrows the `len` bytes starting at `body` for the duration of a parse.
+///
+/// # Safety
+/// `body` must be null or point to at least `len` readable bytes that stay
+/// valid until the parse returns.
+unsafe fn body_slice(body: *const u8, len: usize) -> Result<&'static [u8], Error> {
+    if body.is_null() {
+        return Err(Error::new(MultipartError::NullArgument, "body is null"));
     }
 
-    let data = unsafe { Box::from_raw(form_data) };
+    Ok(std::slice::from_raw_parts(body, len))
+}
 
-    // Free the fields
-    for i in 0..data.field_count {
-        let field = unsafe { &*data.fields.add(i) };
-        unsafe {
-            let _ = CString::from_raw(field.name as *mut c_char);
-            let _ = CString::from_raw(field.value as *mut c_char);
-        }
-    }
+/// Frees the given form data. If the form data is null, does nothing.
+/// Everything the form data points to is freed with it, in a single deallocation,
+/// and its temporary files are deleted.
+///
+/// # Safety
+/// `form_data` must be null or a pointer returned by one of the parse functions
+/// that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn free_multipart_form_data(form_data: *mut FormData) {
+    let data = match form_data.as_ref() {
+        Some(data) => data,
+        None => return,
+    };
+    let base = form_data as *mut u8;
+    let size = data.memory_usage;
 
-    // Free the fields array
-    if !data.fields.is_null() {
-        unsafe {
-            let _ = Vec::from_raw_parts(data.fields, data.field_count, data.field_count);
+    // Spooled files are kept on disk, outside of the allocation.
+    for file in std::slice::from_raw_parts(data.files, data.file_count) {
+        if file.path.is_null() {
+            continue;
         }
-    }
-
-    // Free the files
-    for i in 0..data.file_count {
-        let file = unsafe { &*data.files.add(i) };
-        unsafe {
-            let _ = CString::from_raw(file.filename as *mut c_char);
-            let _ = CString::from_raw(file.content_type as *mut c_char);
-            let _ = CString::from_raw(file.field_name as *mut c_char);
-            let _ = Vec::from_raw_parts(file.content, file.content_length, file.content_length);
+        if file.is_temporary {
+            let _ = std::fs::remove_file(spool::c_path(file.path));
+        } else {
+            // The path was set by `multipart_file_persist`, outside of the allocation.
+            drop(CString::from_raw(file.path as *mut c_char));
         }
     }
 
-    // Free the files array
-    if !data.files.is_null() {
-        unsafe {
-            let _ = Vec::from_raw_parts(data.files, data.file_count, data.file_count);
-        }
-    }
+    arena::free(base, size);
 }
 
-// Explicity shutdown the runtime when the library is unloaded.
+/// Returns the number of bytes retained by the form data, or 0 if it is null.
+/// This is the size of the single allocation holding the form data and everything it points
+/// to; it excludes the caller's body for borrowed form data and files spooled to disk.
+///
+/// # Safety
+/// `form_data` must be null or a pointer returned by one of the parse functions
+/// that has not been freed.
 #[no_mangle]
-pub extern "C" fn shutdown_runtime() {
-    rt().shutdown();
+pub unsafe extern "C" fn form_data_memory_usage(form_data: *const FormData) -> usize {
+    form_data.as_ref().map_or(0, |data| data.memory_usage)
 }
@@ -0,0 +1,766 @@
+use crate::arena::Arena;
+use crate::error::{ffi_guard, ffi_status, Error, MultipartError};
+use crate::schema::{self, FieldType};
+use crate::{FormData, FormField, MultipartFile, PartHeader};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::ffi::CStr;
+use std::hash::BuildHasher;
+use std::os::raw::c_char;
+
+/// Positions of the fields and files by name, built once when the form data is created and
+/// placed in its allocation, so that every lookup takes constant time. Opaque to C.
+#[derive(Debug)]
+pub struct FormIndex {
+    pub(crate) fields: NameTable,
+    pub(crate) files: NameTable,
+    // Keyed per form data, so that a client cannot choose names that collide.
+    hasher: RandomState,
+}
+
+/// An open-addressed hash table of names, each leading to the positions of the items with
+/// that name. The entries are sorted by name, so that the items sharing a name are adjacent.
+#[derive(Debug)]
+pub(crate) struct NameTable {
+    entries: *const IndexEntry,
+    entry_count: usize,
+    // A power of two, at least twice the number of names, so that probes end quickly.
+    buckets: *const Bucket,
+    bucket_count: usize,
+}
+
+/// The name and position of a field or file.
+#[derive(Debug)]
+pub(crate) struct IndexEntry {
+    name: *const c_char,
+    name_len: usize,
+    position: usize,
+}
+
+/// The entries of one name, or an empty bucket if `len` is 0.
+#[derive(Clone, Debug)]
+pub(crate) struct Bucket {
+    start: usize,
+    len: usize,
+}
+
+impl IndexEntry {
+    fn name(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.name as *const u8, self.name_len) }
+    }
+}
+
+/// Places the index of the fields and files in the arena and returns it. Each name is given
+/// both as the string placed in the form data and as its bytes, so that the index can be
+/// built before the form data is written.
+pub(crate) fn lay_out_index<'a>(
+    arena: &mut Arena,
+    field_names: impl Iterator<Item = (*const c_char, &'a [u8])>,
+    file_names: impl Iterator<Item = (*const c_char, &'a [u8])>,
+) -> *mut FormIndex {
+    let index = arena.reserve::<FormIndex>(1);
+    let hasher = RandomState::new();
+    let index_value = FormIndex {
+        fields: lay_out_table(arena, &hasher, field_names),
+        files: lay_out_table(arena, &hasher, file_names),
+        hasher,
+    };
+    arena.write(index, index_value);
+    index
+}
+
+/// Places the entries of the items named `names`, in order, and their buckets in the arena.
+fn lay_out_table<'a>(
+    arena: &mut Arena,
+    hasher: &impl BuildHasher,
+    names: impl Iterator<Item = (*const c_char, &'a [u8])>,
+) -> NameTable {
+    let mut entries: Vec<_> = names
+        .enumerate()
+        .map(|(position, (name, bytes))| {
+            let entry = IndexEntry {
+                name,
+                name_len: bytes.len(),
+                position,
+            };
+            (bytes, entry)
+        })
+        .collect();
+    // The sort is stable, so items with the same name keep their order.
+    entries.sort_by_key(|(name, _)| *name);
+
+    let groups: Vec<_> = entries
+        .chunk_by(|(a, _), (b, _)| a == b)
+        .scan(0, |start, group| {
+            let bucket = Bucket {
+                start: *start,
+                len: group.len(),
+            };
+            *start += group.len();
+            Some((group[0].0, bucket))
+        })
+        .collect();
+
+    let bucket_count = if groups.is_empty() {
+        0
+    } else {
+        (groups.len() * 2).next_power_of_two()
+    };
+    let mut buckets = vec![Bucket { start: 0, len: 0 }; bucket_count];
+    for (name, bucket) in groups {
+        let mut i = hasher.hash_one(name) as usize & (bucket_count - 1);
+        while buckets[i].len != 0 {
+            i = (i + 1) & (bucket_count - 1);
+        }
+        buckets[i] = bucket;
+    }
+
+    let entries: Vec<_> = entries.into_iter().map(|(_, entry)| entry).collect();
+    NameTable {
+        entry_count: entries.len(),
+        entries: arena.alloc_slice(entries),
+        bucket_count,
+        buckets: arena.alloc_slice(buckets),
+    }
+}
+
+impl NameTable {
+    /// Returns the entries with the given name.
+    ///
+    /// # Safety
+    /// The table must have been placed in a form data that has not been freed.
+    unsafe fn named(&self, hasher: &impl BuildHasher, name: &[u8]) -> &[IndexEntry] {
+        if self.bucket_count == 0 {
+            return &[];
+        }
+
+        let entries = std::slice::from_raw_parts(self.entries, self.entry_count);
+        let buckets = std::slice::from_raw_parts(self.buckets, self.bucket_count);
+        let mask = self.bucket_count - 1;
+        let mut i = hasher.hash_one(name) as usize & mask;
+        loop {
+            let bucket = &buckets[i];
+            if bucket.len == 0 {
+                return &[];
+            }
+            let group = &entries[bucket.start..bucket.start + bucket.len];
+            if group[0].name() == name {
+                return group;
+            }
+            i = (i + 1) & mask;
+        }
+    }
+}
+
+impl FormIndex {
+    unsafe fn fields(&self, name: &[u8]) -> &[IndexEntry] {
+        self.fields.named(&self.hasher, name)
+    }
+
+    unsafe fn files(&self, name: &[u8]) -> &[IndexEntry] {
+        self.files.named(&self.hasher, name)
+    }
+}
+
+/// Resolves the arguments shared by every lookup, or returns `None` if any of them is null.
+///
+/// # Safety
+/// `data` must be null or a valid form data; `name` must be null or a valid NUL-terminated string.
+unsafe fn resolve<'a>(
+    data: *const FormData,
+    name: *const c_char,
+) -> Option<(&'a FormData, &'a FormIndex, &'a [u8])> {
+    let data = data.as_ref()?;
+    let index = data.index.as_ref()?;
+    if name.is_null() {
+        return None;
+    }
+    Some((data, index, CStr::from_ptr(name).to_bytes()))
+}
+
+/// Fails with `BorrowedValue` for borrowed form data, whose values are not NUL-terminated,
+/// naming the lookup to use instead.
+///
+/// # Safety
+/// `data` must be null or a valid form data.
+unsafe fn check_terminated(data: *const FormData, instead: &str) -> Result<(), Error> {
+    if data.as_ref().is_some_and(|data| data.borrowed) {
+        return Err(Error::new(
+            MultipartError::BorrowedValue,
+            format!(
+                "values of borrowed form data are not NUL-terminated; use {}",
+                instead
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Copies up to `capacity` items into `out` and returns the total number of matches.
+unsafe fn fill<T>(
+    entries: &[IndexEntry],
+    out: *mut T,
+    capacity: usize,
+    item: impl Fn(usize) -> T,
+) -> usize {
+    if !out.is_null() {
+        for (i, entry) in entries.iter().take(capacity).enumerate() {
+            *out.add(i) = item(entry.position);
+        }
+    }
+    entries.len()
+}
+
+/// Returns the value of the first field with the given name, or null if there is none.
+/// The value is owned by the form data. Borrowed form data, whose values are not
+/// NUL-terminated, always yields null and fails with `BorrowedValue`; see
+/// `multipart_last_error`, and use `form_data_get_field_n` instead.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_field(
+    data: *const FormData,
+    name: *const c_char,
+) -> *const c_char {
+    ffi_guard(|| {
+        check_terminated(data, "form_data_get_field_n")?;
+        Ok(form_data_get_field_n(data, name, std::ptr::null_mut()))
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Returns the value of the first field with the given name and writes its length in bytes
+/// to `len`, unless `len` is null; returns null if there is no such field.
+/// Unlike `form_data_get_field`, this works for borrowed form data, whose values point into
+/// the caller's body and are not NUL-terminated.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `len` must be null or point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_field_n(
+    data: *const FormData,
+    name: *const c_char,
+    len: *mut usize,
+) -> *const c_char {
+    let field = resolve(data, name).and_then(|(data, index, name)| {
+        index
+            .fields(name)
+            .first()
+            .map(|entry| &*data.fields.add(entry.position))
+    });
+
+    match field {
+        Some(field) => {
+            if !len.is_null() {
+                *len = field.value_len;
+            }
+            field.value
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// Writes the values of up to `capacity` fields with the given name to `values`, in the order
+/// they appeared in the body, and returns the total number of fields with that name.
+/// Pass a null `values` to only count them. Borrowed form data, whose values are not
+/// NUL-terminated, always yields 0 and fails with `BorrowedValue`; see `multipart_last_error`,
+/// and use `form_data_get_fields_all_n` instead.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `values` must be null or point to space for at least `capacity` pointers.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_fields_all(
+    data: *const FormData,
+    name: *const c_char,
+    values: *mut *const c_char,
+    capacity: usize,
+) -> usize {
+    ffi_guard(|| {
+        check_terminated(data, "form_data_get_fields_all_n")?;
+        Ok(form_data_get_fields_all_n(
+            data,
+            name,
+            values,
+            std::ptr::null_mut(),
+            capacity,
+        ))
+    })
+    .unwrap_or(0)
+}
+
+/// Writes the values of up to `capacity` fields with the given name to `values` and their
+/// lengths in bytes to `lens`, like `form_data_get_fields_all`, and returns the total number
+/// of fields with that name. Either array may be null. Unlike `form_data_get_fields_all`,
+/// this works for borrowed form data.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `values` and `lens` must each be null or point to space for at least `capacity` items.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_fields_all_n(
+    data: *const FormData,
+    name: *const c_char,
+    values: *mut *const c_char,
+    lens: *mut usize,
+    capacity: usize,
+) -> usize {
+    resolve(data, name).map_or(0, |(data, index, name)| {
+        let entries = index.fields(name);
+        fill(entries, lens, capacity, |i| (*data.fields.add(i)).value_len);
+        fill(entries, values, capacity, |i| (*data.fields.add(i)).value)
+    })
+}
+
+/// Returns the first file uploaded with the given field name, or null if there is none.
+/// The file is owned by the form data.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_file(
+    data: *const FormData,
+    name: *const c_char,
+) -> *mut MultipartFile {
+    resolve(data, name)
+        .and_then(|(data, index, name)| {
+            index
+                .files(name)
+                .first()
+                .map(|entry| data.files.add(entry.position))
+        })
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Writes up to `capacity` files uploaded with the given field name to `files`, in the order
+/// they appeared in the body, and returns the total number of files with that field name.
+/// Pass a null `files` to only count them.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `files` must be null or point to space for at least `capacity` pointers.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_files(
+    data: *const FormData,
+    name: *const c_char,
+    files: *mut *mut MultipartFile,
+    capacity: usize,
+) -> usize {
+    resolve(data, name).map_or(0, |(data, index, name)| {
+        fill(index.files(name), files, capacity, |i| data.files.add(i))
+    })
+}
+
+/// Returns true if the form data has a field or a file with the given name.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_has(data: *const FormData, name: *const c_char) -> bool {
+    resolve(data, name).is_some_and(|(_, index, name)| {
+        !index.fields(name).is_empty() || !index.files(name).is_empty()
+    })
+}
+
+/// Returns the name and the value of the first field with the given name for the typed getters.
+///
+/// # Safety
+/// `data` must be null or a valid form data; `name` must be null or a valid NUL-terminated string.
+unsafe fn typed_field<'a>(
+    data: *const FormData,
+    name: *const c_char,
+) -> Result<(Cow<'a, str>, &'a str), Error> {
+    let (data, index, name) = resolve(data, name).ok_or_else(|| {
+        Error::new(
+            MultipartError::NullArgument,
+            "form data or field name is null",
+        )
+    })?;
+    let field_name = String::from_utf8_lossy(name);
+    let field = match index.fields(name).first() {
+        Some(entry) => &*data.fields.add(entry.position),
+        None => {
+            return Err(Error::new(
+                MultipartError::MissingField,
+                format!("form data has no field {:?}", field_name),
+            ))
+        }
+    };
+
+    // Borrowed values are neither NUL-terminated nor transcoded.
+    let value = std::slice::from_raw_parts(field.value as *const u8, field.value_len);
+    let value = std::str::from_utf8(value).map_err(|_| {
+        Error::new(
+            MultipartError::InvalidUtf8,
+            format!("value of field {:?} is not valid UTF-8", field_name),
+        )
+    })?;
+    Ok((field_name, value))
+}
+
+/// Converts the value of the first field with the given name and writes it to `out`,
+/// unless `out` is null.
+///
+/// # Safety
+/// As for the typed getters.
+unsafe fn get_typed<T>(
+    data: *const FormData,
+    name: *const c_char,
+    out: *mut T,
+    field_type: FieldType,
+    parse: fn(&str) -> Option<T>,
+) -> MultipartError {
+    ffi_status(|| {
+        let (name, value) = typed_field(data, name)?;
+        let value = schema::convert(&name, value, field_type, parse)?;
+        if !out.is_null() {
+            *out = value;
+        }
+        Ok(())
+    })
+}
+
+/// Converts the value of the first field with the given name to a decimal 64-bit signed
+/// integer, ignoring surrounding whitespace, and writes it to `out`.
+/// Returns `MissingField` if there is no such field and `InvalidValue` if the value is not an
+/// integer, leaving `out` untouched; pass a null `out` to only check the value.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `out` must be null or point to an `int64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_i64(
+    data: *const FormData,
+    name: *const c_char,
+    out: *mut i64,
+) -> MultipartError {
+    get_typed(data, name, out, FieldType::Integer, schema::parse_i64)
+}
+
+/// Converts the value of the first field with the given name to a finite number, ignoring
+/// surrounding whitespace, and writes it to `out`. Returns like `form_data_get_i64`.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `out` must be null or point to a `double`.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_f64(
+    data: *const FormData,
+    name: *const c_char,
+    out: *mut f64,
+) -> MultipartError {
+    get_typed(data, name, out, FieldType::Float, schema::parse_f64)
+}
+
+/// Converts the value of the first field with the given name to a boolean and writes it to
+/// `out`: `true`, `1`, `on` and `yes` are true, `false`, `0`, `off` and `no` false, ignoring
+/// case. Returns like `form_data_get_i64`; an unchecked checkbox is sent as no field at all.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `out` must be null or point to a `bool`.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_bool(
+    data: *const FormData,
+    name: *const c_char,
+    out: *mut bool,
+) -> MultipartError {
+    get_typed(data, name, out, FieldType::Boolean, schema::parse_bool)
+}
+
+/// Converts the value of the first field with the given name, an RFC 3339 date such as
+/// `2024-05-17` or date and time such as `2024-05-17T13:45:00+02:00`, to seconds since the
+/// Unix epoch and writes them to `out`. A time without an offset is taken as UTC, and
+/// fractions of a second are dropped. Returns like `form_data_get_i64`.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `out` must be null or point to an `int64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_date(
+    data: *const FormData,
+    name: *const c_char,
+    out: *mut i64,
+) -> MultipartError {
+    get_typed(data, name, out, FieldType::Date, schema::parse_date)
+}
+
+/// Checks that the value of the first field with the given name is valid JSON, and writes the
+/// value and its length in bytes to `json` and `json_len`, unless they are null. The value is
+/// owned by the form data. Returns like `form_data_get_i64`.
+///
+/// # Safety
+/// `data` must be null or a pointer returned by a parse function that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `json` and `json_len` must each be null or point to space for the result.
+#[no_mangle]
+pub unsafe extern "C" fn form_data_get_json(
+    data: *const FormData,
+    name: *const c_char,
+    json: *mut *const c_char,
+    json_len: *mut usize,
+) -> MultipartError {
+    ffi_status(|| {
+        let (name, value) = typed_field(data, name)?;
+        schema::convert(&name, value, FieldType::Json, schema::parse_json)?;
+        if !json.is_null() {
+            *json = value.as_ptr() as *const c_char;
+        }
+        if !json_len.is_null() {
+            *json_len = value.len();
+        }
+        Ok(())
+    })
+}
+
+/// Returns the value of the first header in the array with the given name, ignoring case.
+///
+/// # Safety
+/// `headers` must point to `count` valid headers; `name` must be null or a valid NUL-terminated string.
+unsafe fn find_header(
+    headers: *const PartHeader,
+    count: usize,
+    name: *const c_char,
+) -> *const c_char {
+    if name.is_null() || count == 0 {
+        return std::ptr::null();
+    }
+
+    let name = CStr::from_ptr(name).to_bytes();
+    std::slice::from_raw_parts(headers, count)
+        .iter()
+        .find(|header| {
+            CStr::from_ptr(header.name)
+                .to_bytes()
+                .eq_ignore_ascii_case(name)
+        })
+        .map_or(std::ptr::null(), |header| header.value)
+}
+
+/// Returns the value of the field's header with the given name, ignoring case,
+/// or null if the part had no such header. The value is owned by the form data.
+///
+/// # Safety
+/// `field` must be null or point to a field of a form data that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn form_field_get_header(
+    field: *const FormField,
+    name: *const c_char,
+) -> *const c_char {
+    field.as_ref().map_or(std::ptr::null(), |field| {
+        find_header(field.headers, field.header_count, name)
+    })
+}
+
+/// Returns the value of the file's header with the given name, ignoring case,
+/// or null if the part had no such header. The value is owned by the form data.
+///
+/// # Safety
+/// `file` must be null or point to a file of a form data that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_file_get_header(
+    file: *const MultipartFile,
+    name: *const c_char,
+) -> *const c_char {
+    file.as_ref().map_or(std::ptr::null(), |file| {
+        find_header(file.headers, file.header_count, name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{free_multipart_form_data, parse_multipart_form_data_with_options};
+    use std::ffi::CString;
+    use std::hash::Hasher;
+
+    /// Hashes every name to the last bucket, so that every name collides and probes wrap
+    /// around to the first bucket.
+    struct Colliding;
+
+    impl BuildHasher for Colliding {
+        type Hasher = Colliding;
+
+        fn build_hasher(&self) -> Colliding {
+            Colliding
+        }
+    }
+
+    impl Hasher for Colliding {
+        fn finish(&self) -> u64 {
+            u64::MAX
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    /// Lays out a table of the names in an arena measured first, and checks it against
+    /// `lookups` of names and the positions expected for them.
+    fn check_table(names: &[&str], lookups: &[(&str, &[usize])]) {
+        let names: Vec<CString> = names
+            .iter()
+            .map(|&name| CString::new(name).unwrap())
+            .collect();
+        let lay_out = |arena: &mut Arena| {
+            let table = arena.reserve::<NameTable>(1);
+            let value = lay_out_table(
+                arena,
+                &Colliding,
+                names.iter().map(|name| (name.as_ptr(), name.to_bytes())),
+            );
+            arena.write(table, value);
+            table
+        };
+
+        let mut measured = Arena::measure();
+        lay_out(&mut measured);
+        let mut arena = Arena::allocate(&measured).unwrap();
+        let table = lay_out(&mut arena);
+        assert!(arena.is_full());
+
+        unsafe {
+            for (name, positions) in lookups {
+                let found: Vec<_> = (*table)
+                    .named(&Colliding, name.as_bytes())
+                    .iter()
+                    .map(|entry| entry.position)
+                    .collect();
+                assert_eq!(&found, positions, "{:?}", name);
+            }
+            crate::arena::free(table as *mut u8, arena.size());
+        }
+    }
+
+    #[test]
+    fn colliding_names_wrap_around() {
+        check_table(
+            &["b", "a", "c", "a", "b"],
+            &[
+                ("a", &[1, 3]),
+                ("b", &[0, 4]),
+                ("c", &[2]),
+                ("d", &[]),
+                ("", &[]),
+            ],
+        );
+        // A miss probes past the only name and wraps around to the empty first bucket.
+        check_table(&["a"], &[("a", &[0]), ("b", &[])]);
+        check_table(&[], &[("a", &[])]);
+    }
+
+    /// Parses the body and runs `check` on the form data.
+    fn with_form_data(body: &str, check: impl FnOnce(*const FormData)) {
+        unsafe {
+            let data = parse_multipart_form_data_with_options(
+                c"multipart/form-data; boundary=X".as_ptr(),
+                body.as_ptr(),
+                body.len(),
+                std::ptr::null(),
+            );
+            assert!(!data.is_null());
+            check(data);
+            free_multipart_form_data(data);
+        }
+    }
+
+    fn value(value: *const c_char) -> Option<String> {
+        unsafe {
+            value
+                .as_ref()
+                .map(|_| CStr::from_ptr(value).to_str().unwrap().to_string())
+        }
+    }
+
+    #[test]
+    fn lookups() {
+        let body = "--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\none\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"f\"; filename=\"f.txt\"\r\n\r\nfile\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\n\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ntwo\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nthree\r\n--X--\r\n";
+
+        with_form_data(body, |data| unsafe {
+            assert_eq!(
+                value(form_data_get_field(data, c"a".as_ptr())).unwrap(),
+                "one"
+            );
+            assert_eq!(value(form_data_get_field(data, c"b".as_ptr())).unwrap(), "");
+            assert!(form_data_get_field(data, c"f".as_ptr()).is_null());
+            assert!(form_data_get_field(data, c"A".as_ptr()).is_null());
+            assert!(form_data_get_field(data, std::ptr::null()).is_null());
+            assert!(form_data_get_field(std::ptr::null(), c"a".as_ptr()).is_null());
+
+            let mut len = 0;
+            let one = form_data_get_field_n(data, c"a".as_ptr(), &mut len);
+            assert_eq!((value(one).unwrap().as_str(), len), ("one", 3));
+
+            // Every match is counted, but only `capacity` of them are written.
+            let mut values = [std::ptr::null(); 2];
+            let mut lens = [0; 2];
+            let name = c"a".as_ptr();
+            assert_eq!(
+                form_data_get_fields_all(data, name, std::ptr::null_mut(), 0),
+                3
+            );
+            assert_eq!(
+                form_data_get_fields_all(data, name, values.as_mut_ptr(), 2),
+                3
+            );
+            assert_eq!(values.map(|v| value(v).unwrap()), ["one", "two"]);
+            let count =
+                form_data_get_fields_all_n(data, name, std::ptr::null_mut(), lens.as_mut_ptr(), 2);
+            assert_eq!((count, lens), (3, [3, 3]));
+            let name = c"z".as_ptr();
+            assert_eq!(
+                form_data_get_fields_all(data, name, values.as_mut_ptr(), 2),
+                0
+            );
+
+            let file = form_data_get_file(data, c"f".as_ptr());
+            assert_eq!(value((*file).filename).unwrap(), "f.txt");
+            assert!(form_data_get_file(data, c"a".as_ptr()).is_null());
+            let mut files = [std::ptr::null_mut(); 1];
+            assert_eq!(
+                form_data_get_files(data, c"f".as_ptr(), files.as_mut_ptr(), 1),
+                1
+            );
+            assert_eq!(files[0], file);
+
+            assert!(form_data_has(data, c"a".as_ptr()));
+            assert!(form_data_has(data, c"f".as_ptr()));
+            assert!(!form_data_has(data, c"z".as_ptr()));
+        });
+    }
+
+    #[test]
+    fn lookups_in_an_empty_form() {
+        with_form_data("--X--\r\n", |data| unsafe {
+            assert!(form_data_get_field(data, c"a".as_ptr()).is_null());
+            let count = form_data_get_fields_all_n(
+                data,
+                c"a".as_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0,
+            );
+            assert_eq!(count, 0);
+            assert!(form_data_get_file(data, c"a".as_ptr()).is_null());
+            assert!(!form_data_has(data, c"".as_ptr()));
+        });
+    }
+}
@@ -0,0 +1,395 @@
+use crate::error::{Error, MultipartError};
+use crate::hash::HashAlgorithm;
+use crate::schema::{FieldSchema, FieldType};
+use crate::sniff;
+use multer::{Constraints, SizeLimit};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+
+/// Number of levels of nested multipart parts expanded by default.
+const DEFAULT_NESTING_DEPTH: usize = 1;
+
+/// Ratio of decompressed to compressed size allowed by default.
+const DEFAULT_DECOMPRESSION_RATIO: u64 = 100;
+
+/// Options applied while parsing. For every size and count limit, a value of 0 means
+/// unlimited; a `max_nesting_depth` of 0 means the default of one level, and a
+/// `max_decompression_ratio` of 0 the default of 100, so that a zeroed `ParseOptions`
+/// behaves like the one returned by `parse_options_default`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+    max_total_size: u64,                  // Maximum size of the whole body in bytes.
+    max_file_size: u64,                   // Maximum size of a single file in bytes.
+    max_field_size: u64,                  // Maximum size of a single text field value in bytes.
+    max_parts: usize,                     // Maximum number of parts (fields and files together).
+    max_header_size: usize,               // Maximum size of a single part's headers in bytes.
+    allowed_fields: *const *const c_char, // Array of allowed field names, or null to allow any name.
+    allowed_field_count: usize,           // Number of names in `allowed_fields`.
+    max_memory_size: u64,                 // Spool files larger than this to disk; 0 never spools.
+    temp_dir: *const c_char,              // Directory for spooled files, null for the default.
+    max_nesting_depth: usize,             // Nested multipart levels to expand; 0 for the default.
+    skip_nested_parts: bool,              // Whether to keep nested multipart parts whole.
+    sanitize_filenames: bool,             // Whether to sanitise filenames while parsing.
+    // Array of content type allow-lists, or null to allow any type.
+    content_type_rules: *const ContentTypeRule,
+    content_type_rule_count: usize, // Number of rules in `content_type_rules`.
+    hash_algorithm: u32,            // A `HashAlgorithm` to hash files with while parsing.
+    skip_transfer_decoding: bool,   // Whether to keep base64 and quoted-printable parts encoded.
+    skip_part_decompression: bool,  // Whether to keep parts with a Content-Encoding compressed.
+    max_decompression_ratio: u64,   // Maximum ratio of decompressed to compressed size.
+    // Content-Encoding of the whole body, or null if it is not compressed.
+    content_encoding: *const c_char,
+    // Array of rules that the fields must follow, or null for no schema.
+    field_rules: *const FieldRule,
+    field_rule_count: usize, // Number of rules in `field_rules`.
+}
+
+/// The content types allowed for the files of a field, checked against the type detected from
+/// their content. A `field_name` of `*` applies to the fields without a rule of their own.
+/// Patterns are types such as `image/png` or wildcards such as `image/*`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ContentTypeRule {
+    field_name: *const c_char,           // Field name, or `*` for the others.
+    content_types: *const *const c_char, // Array of allowed content types.
+    content_type_count: usize,           // Number of `content_types`.
+}
+
+/// A rule that every field with the given name must follow, checked once the whole body has
+/// been parsed. Empty values are treated as missing: they are not checked, and do not satisfy
+/// `required`. See `FieldType` for what `min` and `max` bound for each type.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FieldRule {
+    name: *const c_char,    // Field name.
+    field_type: u32,        // A `FieldType` that the values must convert to.
+    required: bool,         // Whether the field must have a non-empty value.
+    has_min: bool,          // Whether `min` applies.
+    min: f64,               // Lower bound, inclusive.
+    has_max: bool,          // Whether `max` applies.
+    max: f64,               // Upper bound, inclusive.
+    pattern: *const c_char, // Regular expression the whole value must match, or null.
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_total_size: 0,
+            max_file_size: 0,
+            max_field_size: 0,
+            max_parts: 0,
+            max_header_size: 0,
+            allowed_fields: std::ptr::null(),
+            allowed_field_count: 0,
+            max_memory_size: 0,
+            temp_dir: std::ptr::null(),
+            max_nesting_depth: DEFAULT_NESTING_DEPTH,
+            skip_nested_parts: false,
+            sanitize_filenames: false,
+            content_type_rules: std::ptr::null(),
+            content_type_rule_count: 0,
+            hash_algorithm: HashAlgorithm::None as u32,
+            skip_transfer_decoding: false,
+            skip_part_decompression: false,
+            max_decompression_ratio: DEFAULT_DECOMPRESSION_RATIO,
+            content_encoding: std::ptr::null(),
+            field_rules: std::ptr::null(),
+            field_rule_count: 0,
+        }
+    }
+}
+
+/// Returns parse options with every size and count limit disabled and every file kept in
+/// memory. Nested multipart parts are expanded one level deep, base64 and quoted-printable
+/// contents are decoded, and compressed parts are decompressed up to 100 times their size.
+#[no_mangle]
+pub extern "C" fn parse_options_default() -> ParseOptions {
+    ParseOptions::default()
+}
+
+/// Options applied while parsing from Rust; the owned counterpart of `ParseOptions`.
+/// For every limit, `None` means unlimited.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Maximum size of the whole body in bytes.
+    pub max_total_size: Option<u64>,
+    /// Maximum size of a single file in bytes.
+    pub max_file_size: Option<u64>,
+    /// Maximum size of a single text field value in bytes.
+    pub max_field_size: Option<u64>,
+    /// Maximum number of parts (fields and files together).
+    pub max_parts: Option<usize>,
+    /// Maximum size of a single part's headers in bytes.
+    pub max_header_size: Option<usize>,
+    /// Field names that are allowed, or `None` to allow any name.
+    pub allowed_fields: Option<Vec<String>>,
+    /// Spool files larger than this to disk; `None` keeps every file in memory.
+    pub max_memory_size: Option<u64>,
+    /// Directory for spooled files, `None` for the system temp directory.
+    pub temp_dir: Option<PathBuf>,
+    /// Levels of nested multipart parts, such as a `multipart/mixed` part holding several
+    /// files, that are expanded into files named after their parent field. Nested parts
+    /// beyond this depth are kept as a single part, and 0 expands none.
+    pub max_nesting_depth: usize,
+    /// Whether to make filenames safe to use as file names with `sanitize_filename`.
+    /// The filename as it was sent is still available from `File::raw_filename`.
+    pub sanitize_filenames: bool,
+    /// Content types allowed for the files of each field, keyed by field name, with `*` for
+    /// the fields without an entry of their own. Files are checked against the content type
+    /// detected from their content, before any of it is spooled to disk, and a mismatch fails
+    /// the parse with `DisallowedContentType`. Patterns are types such as `image/png` or
+    /// wildcards such as `image/*`. Fields without an entry accept any content type.
+    pub allowed_content_types: HashMap<String, Vec<String>>,
+    /// Algorithm to hash the content of each file with as it streams in, so that uploads need
+    /// no second pass; see `File::digest`. `HashAlgorithm::None` disables hashing.
+    pub hash_algorithm: HashAlgorithm,
+    /// Whether to decode the content of parts sent with a base64 or quoted-printable
+    /// Content-Transfer-Encoding. The encoding is still recorded on each file; see
+    /// `File::transfer_encoding`.
+    pub decode_transfer_encoding: bool,
+    /// Content-Encoding of the whole body, such as `gzip`, taken from the request's header;
+    /// `None` if the body is not compressed. Supported codings are `gzip`, `deflate`, `br`
+    /// and `zstd`. The body is decompressed as it is read, and every other limit applies to
+    /// the decompressed content.
+    pub content_encoding: Option<String>,
+    /// Whether to decompress the content of parts sent with a Content-Encoding, with the same
+    /// codings as the body. Parts sent with any other coding are kept as they were sent, and
+    /// the encoding is still recorded on each file; see `File::content_encoding`.
+    pub decompress_parts: bool,
+    /// Maximum ratio of decompressed to compressed size, checked while decompressing the body
+    /// and each part to stop zip bombs early; 100 by default. Content that decompresses to
+    /// 64 KiB or less is never rejected by the ratio. The size limits are also checked as the
+    /// content is decompressed, but with neither, decompressing may take unbounded memory.
+    pub max_decompression_ratio: Option<u64>,
+    /// Rules that the fields must follow, checked once the whole body has been parsed, so that
+    /// a parse fails with `ValidationFailed` and a message listing every violation at once.
+    /// Parses that hand parts to callbacks collect no form, and so do not check it.
+    pub schema: Vec<FieldSchema>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_total_size: None,
+            max_file_size: None,
+            max_field_size: None,
+            max_parts: None,
+            max_header_size: None,
+            allowed_fields: None,
+            max_memory_size: None,
+            temp_dir: None,
+            max_nesting_depth: DEFAULT_NESTING_DEPTH,
+            sanitize_filenames: false,
+            allowed_content_types: HashMap::new(),
+            hash_algorithm: HashAlgorithm::None,
+            decode_transfer_encoding: true,
+            content_encoding: None,
+            decompress_parts: true,
+            max_decompression_ratio: Some(DEFAULT_DECOMPRESSION_RATIO),
+            schema: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Copies the options passed from C. A null pointer yields the default, unlimited options.
+    ///
+    /// # Safety
+    /// `options` must be null or point to a valid `ParseOptions` whose `allowed_fields`
+    /// is null or points to `allowed_field_count` valid NUL-terminated strings, whose
+    /// `temp_dir` and `content_encoding` are null or valid NUL-terminated strings, whose
+    /// `content_type_rules` is null or points to `content_type_rule_count` rules of valid
+    /// strings, and whose `field_rules` is null or points to `field_rule_count` rules whose
+    /// `name` is a valid string and `pattern` null or a valid string.
+    pub(crate) unsafe fn from_ffi(options: *const ParseOptions) -> Result<Options, Error> {
+        let options = match options.as_ref() {
+            Some(options) => options,
+            None => return Ok(Options::default()),
+        };
+
+        let allowed_fields = if options.allowed_fields.is_null() {
+            None
+        } else {
+            Some(strings(
+                options.allowed_fields,
+                options.allowed_field_count,
+                "allowed field name",
+            )?)
+        };
+
+        let mut allowed_content_types = HashMap::new();
+        if !options.content_type_rules.is_null() {
+            let rules = std::slice::from_raw_parts(
+                options.content_type_rules,
+                options.content_type_rule_count,
+            );
+            for rule in rules {
+                let field_name = string(rule.field_name, "content type rule field name")?;
+                let content_types = if rule.content_types.is_null() {
+                    Vec::new()
+                } else {
+                    strings(
+                        rule.content_types,
+                        rule.content_type_count,
+                        "allowed content type",
+                    )?
+                };
+                allowed_content_types.insert(field_name, content_types);
+            }
+        }
+
+        let schema = if options.field_rules.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(options.field_rules, options.field_rule_count)
+                .iter()
+                .map(|rule| {
+                    let pattern = if rule.pattern.is_null() {
+                        None
+                    } else {
+                        Some(string(rule.pattern, "field rule pattern")?)
+                    };
+                    Ok(FieldSchema {
+                        name: string(rule.name, "field rule name")?,
+                        field_type: FieldType::try_from(rule.field_type)?,
+                        required: rule.required,
+                        min: rule.has_min.then_some(rule.min),
+                        max: rule.has_max.then_some(rule.max),
+                        pattern,
+                    })
+                })
+                .collect::<Result<_, Error>>()?
+        };
+
+        let content_encoding = if options.content_encoding.is_null() {
+            None
+        } else {
+            Some(string(options.content_encoding, "content encoding")?)
+        };
+
+        let temp_dir = if options.temp_dir.is_null() {
+            None
+        } else {
+            let temp_dir = CStr::from_ptr(options.temp_dir).to_str().map_err(|_| {
+                Error::new(MultipartError::InvalidUtf8, "temp dir is not valid UTF-8")
+            })?;
+            Some(PathBuf::from(temp_dir))
+        };
+
+        let max_nesting_depth = match options.max_nesting_depth {
+            _ if options.skip_nested_parts => 0,
+            0 => DEFAULT_NESTING_DEPTH,
+            depth => depth,
+        };
+
+        let non_zero_u64 = |value: u64| (value != 0).then_some(value);
+        let non_zero_usize = |value: usize| (value != 0).then_some(value);
+
+        Ok(Options {
+            max_total_size: non_zero_u64(options.max_total_size),
+            max_file_size: non_zero_u64(options.max_file_size),
+            max_field_size: non_zero_u64(options.max_field_size),
+            max_parts: non_zero_usize(options.max_parts),
+            max_header_size: non_zero_usize(options.max_header_size),
+            allowed_fields,
+            max_memory_size: non_zero_u64(options.max_memory_size),
+            temp_dir,
+            max_nesting_depth,
+            sanitize_filenames: options.sanitize_filenames,
+            allowed_content_types,
+            hash_algorithm: HashAlgorithm::try_from(options.hash_algorithm)?,
+            decode_transfer_encoding: !options.skip_transfer_decoding,
+            content_encoding,
+            decompress_parts: !options.skip_part_decompression,
+            max_decompression_ratio: Some(match options.max_decompression_ratio {
+                0 => DEFAULT_DECOMPRESSION_RATIO,
+                ratio => ratio,
+            }),
+            schema,
+        })
+    }
+
+    /// Fails if files of the field may not have the detected content type.
+    pub(crate) fn check_content_type(&self, field: &str, content_type: &str) -> Result<(), Error> {
+        let allowed = match self
+            .allowed_content_types
+            .get(field)
+            .or_else(|| self.allowed_content_types.get("*"))
+        {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+
+        if allowed
+            .iter()
+            .any(|pattern| sniff::matches(pattern, content_type))
+        {
+            return Ok(());
+        }
+
+        Err(Error::new(
+            MultipartError::DisallowedContentType,
+            format!(
+                "file of field {:?} has content type {} which is not allowed",
+                field, content_type
+            ),
+        ))
+    }
+
+    /// Maps the limits that multer can enforce itself onto its constraints.
+    /// The file and field size limits are checked by the parser instead, against the content
+    /// once decoded from its transfer encoding, and once it is known whether a part is a file.
+    pub(crate) fn constraints(&self) -> Constraints {
+        let mut size_limit = SizeLimit::new();
+        if let Some(limit) = self.max_total_size {
+            size_limit = size_limit.whole_stream(limit);
+        }
+
+        let mut constraints = Constraints::new().size_limit(size_limit);
+        if let Some(allowed_fields) = &self.allowed_fields {
+            constraints = constraints.allowed_fields(allowed_fields.clone());
+        }
+        constraints
+    }
+}
+
+/// Copies a string passed from C, naming it `what` in errors.
+///
+/// # Safety
+/// `string` must be null or point to a valid NUL-terminated string.
+unsafe fn string(string: *const c_char, what: &str) -> Result<String, Error> {
+    if string.is_null() {
+        return Err(Error::new(
+            MultipartError::NullArgument,
+            format!("{} is null", what),
+        ));
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map(str::to_string)
+        .map_err(|_| {
+            Error::new(
+                MultipartError::InvalidUtf8,
+                format!("{} is not valid UTF-8", what),
+            )
+        })
+}
+
+/// Copies an array of `count` strings passed from C, naming each `what` in errors.
+///
+/// # Safety
+/// `strings` must point to `count` pointers, each null or pointing to a valid
+/// NUL-terminated string.
+unsafe fn strings(
+    strings: *const *const c_char,
+    count: usize,
+    what: &str,
+) -> Result<Vec<String>, Error> {
+    std::slice::from_raw_parts(strings, count)
+        .iter()
+        .map(|&s| string(s, what))
+        .collect()
+}
@@ -0,0 +1,196 @@
+use crate::boundary::is_valid_boundary;
+use crate::error::{ffi_guard, ffi_status, Error, MultipartError};
+use crate::form::{parse_parts, Form};
+use crate::options::{Options, ParseOptions};
+use crate::sink::{FormCollector, PartSink};
+use crate::{ffi_parse, FormData};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, StreamExt};
+use multer::bytes::Bytes;
+use std::convert::Infallible;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::task::{Context, Poll};
+
+/// Where the parse stands after the last chunk was fed.
+enum State {
+    Parsing(LocalBoxFuture<'static, Result<Form, Error>>),
+    Done(Result<Form, Error>),
+}
+
+/// An incremental parser that is fed the body chunk by chunk.
+/// Opaque to C; created by `multipart_parser_new`.
+pub struct MultipartParser {
+    // Dropped by `finish` to signal the end of the body.
+    sender: Option<UnboundedSender<Bytes>>,
+    state: State,
+}
+
+impl MultipartParser {
+    fn new<K: PartSink + 'static>(boundary: String, options: Options, sink: K) -> Self {
+        let (sender, receiver) = unbounded::<Bytes>();
+        let stream = receiver.map(Ok::<Bytes, Infallible>);
+
+        let parse = async move { parse_parts(stream, &boundary, &options, sink).await };
+
+        MultipartParser {
+            sender: Some(sender),
+            state: State::Parsing(parse.boxed_local()),
+        }
+    }
+
+    /// Creates a parser from the arguments passed from C.
+    ///
+    /// # Safety
+    /// `boundary` must be null or point to a valid NUL-terminated string.
+    /// `options` must be null or point to a valid `ParseOptions`.
+    pub(crate) unsafe fn from_ffi<K: PartSink + 'static>(
+        boundary: *const c_char,
+        options: *const ParseOptions,
+        make_sink: impl FnOnce(&Options) -> K,
+    ) -> Result<Self, Error> {
+        if boundary.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "boundary is null"));
+        }
+
+        let boundary = CStr::from_ptr(boundary)
+            .to_str()
+            .ok()
+            .filter(|boundary| is_valid_boundary(boundary))
+            .ok_or_else(|| {
+                Error::new(
+                    MultipartError::MissingBoundary,
+                    "invalid multipart boundary",
+                )
+            })?;
+        let options = Options::from_ffi(options)?;
+        let sink = make_sink(&options);
+
+        Ok(MultipartParser::new(boundary.to_string(), options, sink))
+    }
+
+    /// Drives the parse as far as the chunks fed so far allow.
+    /// The parse only waits on the channel, which is never woken from elsewhere,
+    /// so it is polled directly instead of being handed to a runtime.
+    fn poll(&mut self) {
+        if let State::Parsing(parse) = &mut self.state {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            if let Poll::Ready(result) = parse.as_mut().poll(&mut cx) {
+                self.state = State::Done(result);
+            }
+        }
+    }
+
+    fn feed(&mut self, chunk: Bytes) -> Result<(), Error> {
+        match &self.state {
+            State::Done(Err(err)) => return Err(err.clone()),
+            // Anything after the closing boundary is epilogue and is ignored.
+            State::Done(Ok(_)) => return Ok(()),
+            State::Parsing(_) => {}
+        }
+
+        if let Some(sender) = &self.sender {
+            // The receiver lives in the parse future, which is still pending.
+            let _ = sender.unbounded_send(chunk);
+        }
+
+        self.poll();
+
+        match &self.state {
+            State::Done(Err(err)) => Err(err.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(mut self) -> Result<Form, Error> {
+        self.sender = None;
+        self.poll();
+
+        match self.state {
+            State::Done(result) => result,
+            State::Parsing(_) => Err(Error::new(
+                MultipartError::Internal,
+                "parser did not complete after the end of the body",
+            )),
+        }
+    }
+}
+
+/// Creates a parser that is fed the body incrementally with `multipart_parser_feed`.
+/// `boundary` is the bare boundary, without the leading `--`.
+/// A null `options` applies no limits and keeps every file in memory.
+/// Returns null on failure; see `multipart_last_error` for the reason.
+/// The parser must be released with either `multipart_parser_finish` or `multipart_parser_free`.
+///
+/// # Safety
+/// `boundary` must be null or point to a valid NUL-terminated string.
+/// `options` must be null or point to a valid `ParseOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_parser_new(
+    boundary: *const c_char,
+    options: *const ParseOptions,
+) -> *mut MultipartParser {
+    let parser = ffi_guard(|| MultipartParser::from_ffi(boundary, options, FormCollector::new));
+
+    parser.map_or(std::ptr::null_mut(), |parser| {
+        Box::into_raw(Box::new(parser))
+    })
+}
+
+/// Feeds the next `len` bytes of the body to the parser, parsing as much as possible.
+/// The bytes are copied, so the buffer can be reused as soon as the call returns.
+/// Once the parser has failed, every later call returns the same error.
+///
+/// # Safety
+/// `parser` must be null or a pointer returned by `multipart_parser_new` that has not been released.
+/// `buf` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_parser_feed(
+    parser: *mut MultipartParser,
+    buf: *const u8,
+    len: usize,
+) -> MultipartError {
+    ffi_status(|| {
+        let parser = parser
+            .as_mut()
+            .ok_or_else(|| Error::new(MultipartError::NullArgument, "parser is null"))?;
+        if buf.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "buffer is null"));
+        }
+
+        let chunk = Bytes::copy_from_slice(std::slice::from_raw_parts(buf, len));
+        parser.feed(chunk)
+    })
+}
+
+/// Signals the end of the body and returns the parsed form data.
+/// The parser is released whether or not parsing succeeded.
+/// Returns null on failure, e.g. if the body ended before the closing boundary;
+/// see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
+///
+/// # Safety
+/// `parser` must be null or a pointer returned by `multipart_parser_new` that has not been released.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_parser_finish(parser: *mut MultipartParser) -> *mut FormData {
+    ffi_parse(|| {
+        if parser.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "parser is null"));
+        }
+
+        Box::from_raw(parser).finish()
+    })
+}
+
+/// Releases the parser without finishing the parse, e.g. when the connection is dropped.
+/// If the parser is null, does nothing.
+///
+/// # Safety
+/// `parser` must be null or a pointer returned by `multipart_parser_new` that has not been released.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_parser_free(parser: *mut MultipartParser) {
+    if !parser.is_null() {
+        drop(Box::from_raw(parser));
+    }
+}
@@ -0,0 +1,90 @@
+//! Drives the async parser to completion for the blocking entry points.
+//!
+//! By default every thread that parses gets its own current-thread Tokio runtime.
+//! Built with `default-features = false`, which turns off the `tokio` feature, the parser is
+//! driven by `futures::executor::block_on` instead and Tokio is not linked at all.
+
+#[cfg(not(feature = "tokio"))]
+use crate::error::Error;
+#[cfg(not(feature = "tokio"))]
+use std::future::Future;
+
+#[cfg(feature = "tokio")]
+mod tokio_runtime {
+    use crate::error::{Error, MultipartError};
+    use std::future::Future;
+    use std::sync::Mutex;
+    use thread_local::ThreadLocal;
+    use tokio::runtime;
+
+    // One current-thread runtime per thread that parses, so that parses on different threads
+    // never contend. A thread's slot is only locked to fetch or shut down its runtime, never
+    // while parsing.
+    static RUNTIMES: ThreadLocal<Mutex<Option<runtime::Runtime>>> = ThreadLocal::new();
+
+    /// Returns a handle to the calling thread's runtime, starting one on first use
+    /// or after `shutdown_runtime`.
+    fn runtime_handle() -> Result<runtime::Handle, Error> {
+        // A poisoned lock only means a parse panicked; the runtime itself is still usable.
+        let mut slot = RUNTIMES
+            .get_or_default()
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(runtime) = slot.as_ref() {
+            return Ok(runtime.handle().clone());
+        }
+
+        // Only the scheduler is needed; see `block_on`.
+        let runtime = runtime::Builder::new_current_thread()
+            .build()
+            .map_err(|err| {
+                Error::new(
+                    MultipartError::Internal,
+                    format!("failed to create Tokio runtime: {}", err),
+                )
+            })?;
+        let handle = runtime.handle().clone();
+        *slot = Some(runtime);
+        Ok(handle)
+    }
+
+    /// Runs the future to completion on the calling thread's runtime.
+    /// Parsing needs neither I/O nor timers, so the handle can drive it without the runtime's lock.
+    pub fn block_on<F: Future>(future: F) -> Result<F::Output, Error> {
+        Ok(runtime_handle()?.block_on(future))
+    }
+
+    pub fn shutdown() {
+        for slot in RUNTIMES.iter() {
+            let runtime = slot
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take();
+
+            if let Some(runtime) = runtime {
+                runtime.shutdown_timeout(std::time::Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) use tokio_runtime::block_on;
+
+/// Runs the future to completion on the calling thread.
+/// The body is already in memory or fed by the caller, so the parse never waits on I/O
+/// and a minimal executor is enough.
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, Error> {
+    Ok(futures::executor::block_on(future))
+}
+
+/// Shuts down the runtime of every thread that has parsed, e.g. when the library is unloaded.
+/// Calling it more than once is harmless, and a later parse starts a new runtime on its thread.
+/// Does nothing when the library is built without Tokio.
+#[no_mangle]
+pub extern "C" fn shutdown_runtime() {
+    #[cfg(feature = "tokio")]
+    tokio_runtime::shutdown();
+}
@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest sanitised filename in bytes, as allowed by most filesystems.
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Longest extension, including its dot, that is kept when a filename is shortened.
+const MAX_EXTENSION_LEN: usize = 16;
+
+/// Name given to a file whose name is empty once sanitised.
+const FALLBACK_FILENAME: &str = "unnamed";
+
+/// Characters that Windows does not allow in filenames.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Device names reserved by Windows, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a filename sent by a client safe to use as the name of a file in a directory:
+///
+/// - directory components are dropped, taking both `/` and `\` as separators, as is a drive
+///   letter such as `C:`;
+/// - the name is normalised to Unicode NFC;
+/// - control characters and bidirectional overrides are removed, and the characters that
+///   Windows does not allow are replaced by `_`;
+/// - leading spaces and trailing dots and spaces are trimmed, so `.` and `..` become empty;
+/// - a Windows device name such as `CON` or `com1.txt` is prefixed with `_`;
+/// - the name is shortened to 255 bytes, keeping its extension;
+/// - an empty name becomes `unnamed`.
+///
+/// Returns the name borrowed as it is if it was already safe, or the sanitised name otherwise.
+pub fn sanitize_filename(filename: &str) -> Cow<'_, str> {
+    // `rsplit` always yields at least one item.
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = match name.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => &name[2..],
+        _ => name,
+    };
+
+    let name: String = name
+        .nfc()
+        .filter(|&c| !is_control(c))
+        .map(|c| if RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    let mut name = name
+        .trim_start_matches(' ')
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+    }
+
+    if name.len() > MAX_FILENAME_LEN {
+        name = shorten(&name);
+    }
+    if name.is_empty() {
+        name = FALLBACK_FILENAME.to_string();
+    }
+
+    if name == filename {
+        Cow::Borrowed(filename)
+    } else {
+        Cow::Owned(name)
+    }
+}
+
+/// Returns true for control characters and for the formatting characters that change the
+/// direction of text, which can make `exe.txt` display as `txt.exe`.
+fn is_control(c: char) -> bool {
+    c.is_control()
+        || matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Shortens the name to `MAX_FILENAME_LEN` bytes on a character boundary, keeping a short
+/// extension intact.
+fn shorten(name: &str) -> String {
+    let extension = name
+        .rfind('.')
+        .filter(|&dot| dot > 0 && name.len() - dot <= MAX_EXTENSION_LEN)
+        .map_or("", |dot| &name[dot..]);
+    let stem = &name[..name.len() - extension.len()];
+
+    let mut end = MAX_FILENAME_LEN - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end_matches(['.', ' ']), extension)
+}
+
+/// Sanitises `filename` like `sanitize_filename` in Rust, as applied by the
+/// `sanitize_filenames` parse option, and writes the result to `out` as a NUL-terminated
+/// string of at most `capacity` bytes, terminator included. Sanitised names are at most 255
+/// bytes long, so a buffer of 256 bytes always holds the whole name.
+/// Malformed UTF-8 sequences in `filename` are replaced by U+FFFD.
+/// If `altered` is not null, it is set to whether the name was changed.
+/// Returns the length of the sanitised name in bytes, excluding the terminator, or 0 if
+/// `filename` is null. Pass a null `out` to only compute the length.
+///
+/// # Safety
+/// `filename` must be null or point to a valid NUL-terminated string.
+/// `out` must be null or point to at least `capacity` writable bytes.
+/// `altered` must be null or point to a writable `bool`.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_sanitize_filename(
+    filename: *const c_char,
+    out: *mut c_char,
+    capacity: usize,
+    altered: *mut bool,
+) -> usize {
+    if filename.is_null() {
+        return 0;
+    }
+
+    let filename = CStr::from_ptr(filename).to_string_lossy();
+    let sanitized = sanitize_filename(&filename);
+    if let Some(altered) = altered.as_mut() {
+        *altered = matches!(filename, Cow::Owned(_)) || matches!(sanitized, Cow::Owned(_));
+    }
+
+    if !out.is_null() && capacity > 0 {
+        let len = sanitized.len().min(capacity - 1);
+        std::ptr::copy_nonoverlapping(sanitized.as_ptr(), out as *mut u8, len);
+        *out.add(len) = 0;
+    }
+    sanitized.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cases: &[(&str, &str)]) {
+        for &(filename, expected) in cases {
+            assert_eq!(sanitize_filename(filename), expected, "{:?}", filename);
+        }
+    }
+
+    #[test]
+    fn safe_names_are_borrowed() {
+        for filename in [
+            "report.pdf",
+            "photo 1.JPG",
+            ".htaccess",
+            "naïve",
+            "日本語.txt",
+        ] {
+            assert!(matches!(sanitize_filename(filename), Cow::Borrowed(_)));
+        }
+    }
+
+    #[test]
+    fn directories_are_dropped() {
+        check(&[
+            ("../../etc/passwd", "passwd"),
+            ("..\\..\\windows\\win.ini", "win.ini"),
+            ("/etc/passwd", "passwd"),
+            ("\\\\server\\share\\file.txt", "file.txt"),
+            ("C:\\Users\\me\\file.txt", "file.txt"),
+            ("c:file.txt", "file.txt"),
+            ("dir/..\\..", "unnamed"),
+            ("file.txt/", "unnamed"),
+            ("1:file.txt", "1_file.txt"),
+        ]);
+    }
+
+    #[test]
+    fn control_and_reserved_characters() {
+        check(&[
+            ("a\0b.txt", "ab.txt"),
+            ("a\r\nb\t.txt", "ab.txt"),
+            ("a\u{7f}b\u{85}.txt", "ab.txt"),
+            ("invoice\u{202E}fdp.exe", "invoicefdp.exe"),
+            ("\u{2066}a\u{2069}\u{200F}.txt", "a.txt"),
+            ("a<b>c:d\"e|f?g*.txt", "a_b_c_d_e_f_g_.txt"),
+            ("e\u{301}te\u{301}.txt", "été.txt"),
+        ]);
+    }
+
+    #[test]
+    fn reserved_device_names() {
+        check(&[
+            ("CON", "_CON"),
+            ("con", "_con"),
+            ("NUL.txt", "_NUL.txt"),
+            ("com1.tar.gz", "_com1.tar.gz"),
+            ("LPT9 .txt", "_LPT9 .txt"),
+            ("aux.", "_aux"),
+            ("CONSOLE.txt", "CONSOLE.txt"),
+            ("COM10", "COM10"),
+            ("my con.txt", "my con.txt"),
+        ]);
+    }
+
+    #[test]
+    fn dots_and_spaces_are_trimmed() {
+        check(&[
+            ("file.txt.", "file.txt"),
+            ("file.txt . .", "file.txt"),
+            ("  file.txt", "file.txt"),
+            ("..hidden", "..hidden"),
+        ]);
+    }
+
+    #[test]
+    fn empty_names_fall_back() {
+        check(&[
+            ("", "unnamed"),
+            (".", "unnamed"),
+            ("..", "unnamed"),
+            (" . . ", "unnamed"),
+            ("C:", "unnamed"),
+            ("\0\u{1}\u{202E}", "unnamed"),
+        ]);
+    }
+
+    #[test]
+    fn long_names_are_shortened() {
+        let long = format!("{}.txt", "a".repeat(300));
+        assert_eq!(sanitize_filename(&long), format!("{}.txt", "a".repeat(251)));
+
+        // Multi-byte characters are never split.
+        let long = format!("{}.txt", "é".repeat(200));
+        assert_eq!(sanitize_filename(&long), format!("{}.txt", "é".repeat(125)));
+
+        // An extension too long to be one is cut like the rest of the name.
+        let long = format!("a.{}", "b".repeat(300));
+        assert_eq!(sanitize_filename(&long), format!("a.{}", "b".repeat(253)));
+
+        // Dots and spaces left at the end of the shortened stem are trimmed.
+        let long = format!("{} .{}.pdf", "a".repeat(249), "b".repeat(40));
+        assert_eq!(sanitize_filename(&long), format!("{}.pdf", "a".repeat(249)));
+
+        let exact = "a".repeat(255);
+        assert!(matches!(sanitize_filename(&exact), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn sanitize_from_c() {
+        let mut out = [0x7f as c_char; 8];
+        let mut altered = false;
+        unsafe {
+            let len = multipart_sanitize_filename(
+                c"../a?b.txt".as_ptr(),
+                out.as_mut_ptr(),
+                out.len(),
+                &mut altered,
+            );
+            assert_eq!(len, 7);
+            assert_eq!(CStr::from_ptr(out.as_ptr()), c"a_b.txt");
+            assert!(altered);
+
+            // The name is cut to fit, but its full length is returned.
+            let len = multipart_sanitize_filename(
+                c"report.pdf".as_ptr(),
+                out.as_mut_ptr(),
+                4,
+                &mut altered,
+            );
+            assert_eq!(len, 10);
+            assert_eq!(CStr::from_ptr(out.as_ptr()), c"rep");
+            assert!(!altered);
+
+            let len = multipart_sanitize_filename(
+                c"CON".as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+            );
+            assert_eq!(len, 4);
+            assert_eq!(
+                multipart_sanitize_filename(std::ptr::null(), out.as_mut_ptr(), 8, &mut altered),
+                0
+            );
+        }
+    }
+}
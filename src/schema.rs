@@ -0,0 +1,441 @@
+use crate::error::{Error, MultipartError};
+use crate::form::Form;
+use regex::Regex;
+
+/// Type that the values of a field must convert to.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldType {
+    /// Any text; `min` and `max` bound its length in characters.
+    #[default]
+    Text = 0,
+    /// A decimal 64-bit signed integer; `min` and `max` bound its value.
+    Integer = 1,
+    /// A finite decimal number; `min` and `max` bound its value.
+    Float = 2,
+    /// `true`, `false`, `1`, `0`, `on`, `off`, `yes` or `no`, ignoring case.
+    Boolean = 3,
+    /// An RFC 3339 date such as `2024-05-17`, or date and time such as `2024-05-17T13:45Z`,
+    /// read as a Unix timestamp in seconds; `min` and `max` bound the timestamp.
+    /// A time without an offset is taken as UTC, and fractions of a second are dropped.
+    Date = 4,
+    /// A JSON document.
+    Json = 5,
+    /// An uploaded file instead of a text field; `min` and `max` bound its size in bytes and
+    /// `pattern` applies to its filename.
+    File = 6,
+}
+
+impl TryFrom<u32> for FieldType {
+    type Error = Error;
+
+    /// Converts the value of a `FieldType` passed from C, failing with `InvalidSchema` if it
+    /// is not one of the types.
+    fn try_from(value: u32) -> Result<Self, Error> {
+        Ok(match value {
+            0 => FieldType::Text,
+            1 => FieldType::Integer,
+            2 => FieldType::Float,
+            3 => FieldType::Boolean,
+            4 => FieldType::Date,
+            5 => FieldType::Json,
+            6 => FieldType::File,
+            _ => {
+                return Err(Error::new(
+                    MultipartError::InvalidSchema,
+                    format!("field type {} is not valid", value),
+                ))
+            }
+        })
+    }
+}
+
+impl FieldType {
+    fn description(self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Integer => "a valid integer",
+            FieldType::Float => "a valid number",
+            FieldType::Boolean => "a valid boolean",
+            FieldType::Date => "a valid date",
+            FieldType::Json => "valid JSON",
+            FieldType::File => "a file",
+        }
+    }
+
+    /// What `min` and `max` bound, for error messages.
+    fn measure(self) -> &'static str {
+        match self {
+            FieldType::Text => "length",
+            FieldType::Date => "timestamp",
+            FieldType::File => "size",
+            _ => "value",
+        }
+    }
+}
+
+/// A rule that every field with the given name must follow; see `Options::schema`.
+/// Empty values are treated as missing: they are not checked, and do not satisfy `required`.
+#[derive(Clone, Debug, Default)]
+pub struct FieldSchema {
+    /// Name of the field.
+    pub name: String,
+    /// Type that the values must convert to.
+    pub field_type: FieldType,
+    /// Whether the form must have a non-empty value, or a file, with this name.
+    pub required: bool,
+    /// Lower bound, inclusive, of what the field type measures; ignored by booleans and JSON.
+    pub min: Option<f64>,
+    /// Upper bound, inclusive, of what the field type measures; ignored by booleans and JSON.
+    pub max: Option<f64>,
+    /// Regular expression that the whole value must match, as with the HTML `pattern`
+    /// attribute, e.g. `[a-z]+` rejects `abc1`.
+    pub pattern: Option<String>,
+}
+
+/// Converts the value of field `name` with `parse`, failing with `InvalidValue` if it is not
+/// of the field type.
+pub(crate) fn convert<T>(
+    name: &str,
+    value: &str,
+    field_type: FieldType,
+    parse: fn(&str) -> Option<T>,
+) -> Result<T, Error> {
+    parse(value).ok_or_else(|| {
+        Error::new(
+            MultipartError::InvalidValue,
+            format!("field {:?} is not {}", name, field_type.description()),
+        )
+    })
+}
+
+pub(crate) fn parse_i64(value: &str) -> Option<i64> {
+    value.trim().parse().ok()
+}
+
+pub(crate) fn parse_f64(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|value: &f64| value.is_finite())
+}
+
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    let value = value.trim();
+    let is = |words: &[&str]| words.iter().any(|word| value.eq_ignore_ascii_case(word));
+
+    if is(&["true", "1", "on", "yes"]) {
+        Some(true)
+    } else if is(&["false", "0", "off", "no"]) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn parse_json(value: &str) -> Option<serde_json::Value> {
+    serde_json::from_str(value).ok()
+}
+
+/// Parses an RFC 3339 date, optionally followed by a time and an offset, into a Unix timestamp.
+/// The time may leave out its seconds, as HTML `datetime-local` inputs do.
+pub(crate) fn parse_date(value: &str) -> Option<i64> {
+    let value = value.trim().as_bytes();
+    let (date, rest) = (value.get(..10)?, &value[10..]);
+    if date[4] != b'-' || date[7] != b'-' {
+        return None;
+    }
+
+    let (year, month, day) = (
+        digits(&date[..4])?,
+        digits(&date[5..7])?,
+        digits(&date[8..10])?,
+    );
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let date = days_from_civil(year, month, day) * 86400;
+
+    let rest = match rest.split_first() {
+        None => return Some(date),
+        Some((b'T' | b't' | b' ', rest)) => rest,
+        Some(_) => return None,
+    };
+    if rest.get(2) != Some(&b':') {
+        return None;
+    }
+    let (hour, minute) = (digits(rest.get(..2)?)?, digits(rest.get(3..5)?)?);
+
+    let mut rest = &rest[5..];
+    let mut second = 0;
+    if let Some(seconds) = rest.strip_prefix(b":") {
+        second = digits(seconds.get(..2)?)?;
+        rest = &seconds[2..];
+        if let Some(fraction) = rest.strip_prefix(b".") {
+            let len = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+            if len == 0 {
+                return None;
+            }
+            rest = &fraction[len..];
+        }
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let offset = match rest {
+        [] | [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), hour @ .., b':', m1, m2] if hour.len() == 2 => {
+            let (hour, minute) = (digits(hour)?, digits(&[*m1, *m2])?);
+            if hour > 23 || minute > 59 {
+                return None;
+            }
+            let offset = hour * 3600 + minute * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    Some(date + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Parses a run of ASCII digits.
+fn digits(digits: &[u8]) -> Option<i64> {
+    digits.iter().try_fold(0, |value, &digit| {
+        digit
+            .is_ascii_digit()
+            .then(|| value * 10 + i64::from(digit - b'0'))
+    })
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years are counted from March, so that the leap day comes last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Checks the form against every rule, failing with `ValidationFailed` and a message listing
+/// every violation, or with `InvalidSchema` if a pattern is not a valid regular expression.
+pub(crate) fn validate(form: &Form, schema: &[FieldSchema]) -> Result<(), Error> {
+    let mut violations = Vec::new();
+
+    for rule in schema {
+        let pattern = match &rule.pattern {
+            Some(pattern) => Some(Regex::new(&format!("^(?:{})$", pattern)).map_err(|err| {
+                Error::new(
+                    MultipartError::InvalidSchema,
+                    format!("pattern of field {:?} is not valid: {}", rule.name, err),
+                )
+            })?),
+            None => None,
+        };
+
+        let mut present = false;
+        if rule.field_type == FieldType::File {
+            // Browsers send an empty part without a filename when no file was chosen.
+            for file in form.files_named(&rule.name) {
+                if file.filename().is_empty() && file.is_empty() {
+                    continue;
+                }
+                present = true;
+                let result = check_bounds(rule, file.len() as f64)
+                    .and_then(|()| check_pattern(rule, pattern.as_ref(), file.filename()));
+                violations.extend(result.err());
+            }
+        } else {
+            for field in form.fields_named(&rule.name) {
+                if field.value().is_empty() {
+                    continue;
+                }
+                present = true;
+                violations.extend(check_value(rule, pattern.as_ref(), field.value()).err());
+            }
+        }
+
+        if rule.required && !present {
+            violations.push(format!("field {:?} is required", rule.name));
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(
+        MultipartError::ValidationFailed,
+        format!("form data failed validation: {}", violations.join("; ")),
+    ))
+}
+
+/// Checks a value of a text field, returning what is wrong with it.
+fn check_value(rule: &FieldSchema, pattern: Option<&Regex>, value: &str) -> Result<(), String> {
+    let invalid = || {
+        format!(
+            "field {:?} is not {}",
+            rule.name,
+            rule.field_type.description()
+        )
+    };
+    let measure = match rule.field_type {
+        FieldType::Text => Some(value.chars().count() as f64),
+        FieldType::Integer => Some(parse_i64(value).ok_or_else(invalid)? as f64),
+        FieldType::Float => Some(parse_f64(value).ok_or_else(invalid)?),
+        FieldType::Date => Some(parse_date(value).ok_or_else(invalid)? as f64),
+        FieldType::Boolean => parse_bool(value).map(|_| None).ok_or_else(invalid)?,
+        FieldType::Json => parse_json(value).map(|_| None).ok_or_else(invalid)?,
+        FieldType::File => unreachable!("files are checked separately"),
+    };
+
+    if let Some(measure) = measure {
+        check_bounds(rule, measure)?;
+    }
+    check_pattern(rule, pattern, value)
+}
+
+fn check_bounds(rule: &FieldSchema, measure: f64) -> Result<(), String> {
+    let what = rule.field_type.measure();
+    if let Some(min) = rule.min.filter(|&min| measure < min) {
+        return Err(format!(
+            "{} of field {:?} is below the minimum of {}",
+            what, rule.name, min
+        ));
+    }
+    if let Some(max) = rule.max.filter(|&max| measure > max) {
+        return Err(format!(
+            "{} of field {:?} is above the maximum of {}",
+            what, rule.name, max
+        ));
+    }
+    Ok(())
+}
+
+fn check_pattern(rule: &FieldSchema, pattern: Option<&Regex>, value: &str) -> Result<(), String> {
+    match pattern {
+        Some(pattern) if !pattern.is_match(value) => Err(format!(
+            "field {:?} does not match the pattern {:?}",
+            rule.name,
+            rule.pattern.as_deref().unwrap_or_default()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-05-17"), Some(1715904000));
+        assert_eq!(parse_date(" 2024-05-17 "), Some(1715904000));
+        assert_eq!(parse_date("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse_date("9999-12-31T23:59:59Z"), Some(253402300799));
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(parse_date("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_date("2024-03-01"), Some(1709251200));
+        assert_eq!(parse_date("2000-02-29"), Some(951782400));
+        assert_eq!(parse_date("1600-02-29"), Some(-11670998400));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert_eq!(parse_date("2024-02-30"), None);
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_date("2024-05-17T13:45:30+02:00"), Some(1715946330));
+        assert_eq!(parse_date("2024-05-17T11:45:30z"), Some(1715946330));
+        assert_eq!(parse_date("2024-05-17T13:45-07:30"), Some(1715980500));
+        assert_eq!(
+            parse_date("2024-05-17T00:00:00-00:00"),
+            parse_date("2024-05-17")
+        );
+        // The offset may move the time to another day.
+        assert_eq!(parse_date("2024-05-17T23:30:00-01:00"), Some(1715992200));
+    }
+
+    #[test]
+    fn fractional_seconds_are_dropped() {
+        assert_eq!(parse_date("2024-05-17T13:45:30.5+02:00"), Some(1715946330));
+        assert_eq!(
+            parse_date("2024-05-17T13:45:30.999999999Z"),
+            parse_date("2024-05-17T13:45:30Z")
+        );
+        assert_eq!(parse_date("2024-05-17T13:45:30.Z"), None);
+        // Only seconds may have a fraction.
+        assert_eq!(parse_date("2024-05-17T13:45.5Z"), None);
+    }
+
+    #[test]
+    fn times_without_seconds_or_offset() {
+        // As sent by `datetime-local` inputs, and taken as UTC.
+        assert_eq!(parse_date("2024-05-17T13:45"), Some(1715953500));
+        assert_eq!(parse_date("2024-05-17 13:45"), Some(1715953500));
+        assert_eq!(parse_date("2024-05-17t13:45:00"), Some(1715953500));
+    }
+
+    #[test]
+    fn malformed_dates() {
+        for value in [
+            "",
+            "2024",
+            "2024-5-17",
+            "2024/05/17",
+            "24-05-17",
+            "2024-00-10",
+            "2024-13-01",
+            "2024-05-00",
+            "2024-05-32",
+            "+2024-05-17",
+            "2024-05-17T",
+            "2024-05-17X13:45",
+            "2024-05-17T1:45",
+            "2024-05-17T13",
+            "2024-05-17T13:4",
+            "2024-05-17T24:00",
+            "2024-05-17T13:60",
+            "2024-05-17T13:45:60",
+            "2024-05-17T13:45:3",
+            "2024-05-17T13:45+2",
+            "2024-05-17T13:45+0200",
+            "2024-05-17T13:45+24:00",
+            "2024-05-17T13:45+02:60",
+            "2024-05-17T13:45 Z",
+            "2024-05-17T13:45ZZ",
+            "2024-05-17T13:45:30.5.5Z",
+            "2024-05-17T13:45:30+02:00junk",
+            "2024-05-1７",
+        ] {
+            assert_eq!(parse_date(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn field_types_from_ffi() {
+        assert_eq!(FieldType::try_from(0).unwrap(), FieldType::Text);
+        assert_eq!(FieldType::try_from(6).unwrap(), FieldType::File);
+        let err = FieldType::try_from(7).unwrap_err();
+        assert_eq!(err.code(), MultipartError::InvalidSchema);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::disposition::{self, FileName};
+use crate::error::Error;
+use crate::form::{Field, File, Form};
+use crate::hash::Digest;
+use crate::options::Options;
+use crate::spool::{FileContent, SpoolBuffer};
+use encoding_rs::{Encoding, UTF_8};
+use mime::Mime;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// What is known about a part once its headers have been read.
+pub(crate) struct PartMeta {
+    pub name: String,
+    pub file_name: Option<FileName>,
+    pub content_type: Option<Mime>,
+    // Set for files once the start of their content has been read.
+    pub detected_content_type: Option<&'static str>,
+    // Lowercase Content-Transfer-Encoding of the part, if it has one.
+    pub transfer_encoding: Option<String>,
+    // Lowercase Content-Encoding of the part, if it has one.
+    pub content_encoding: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl PartMeta {
+    pub fn new(name: String, field: &multer::Field<'_>) -> Self {
+        let headers = field
+            .headers()
+            .iter()
+            .map(|(key, value)| {
+                (
+                    key.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+
+        PartMeta {
+            name,
+            file_name: field
+                .headers()
+                .get("content-disposition")
+                .and_then(|value| disposition::file_name(value.as_bytes())),
+            content_type: field.content_type().cloned(),
+            detected_content_type: None,
+            transfer_encoding: field
+                .headers()
+                .get("content-transfer-encoding")
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_ascii_lowercase()),
+            content_encoding: field
+                .headers()
+                .get("content-encoding")
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_ascii_lowercase()),
+            headers,
+        }
+    }
+
+    /// Parts with a filename are files, everything else is a text field.
+    pub fn is_file(&self) -> bool {
+        self.file_name.is_some()
+    }
+}
+
+/// Receives the parts of the body as the parser reads them.
+/// Any error aborts the parse.
+pub(crate) trait PartSink {
+    /// Called once the headers of a part have been read.
+    fn begin(&mut self, part: PartMeta) -> Result<(), Error>;
+
+    /// Called for each chunk of the current part's content.
+    fn data(&mut self, chunk: &[u8]) -> Result<(), Error>;
+
+    /// Called after the last chunk of the current part, with the digest of its content if it
+    /// is a file and files are hashed.
+    fn end(&mut self, digest: Option<Digest>) -> Result<(), Error>;
+
+    /// Returns what the sink collected once the whole body has been parsed, failing if it
+    /// does not follow the schema of the options.
+    fn finish(self, options: &Options) -> Result<Form, Error>;
+}
+
+/// Builds a form from parts whose content has been read completely.
+#[derive(Default)]
+pub(crate) struct FormBuilder {
+    form: Form,
+    // Charset declared by each text field's Content-Type, in the same order as the fields.
+    charsets: Vec<Option<&'static Encoding>>,
+}
+
+impl FormBuilder {
+    pub fn push(&mut self, part: PartMeta, content: FileContent, digest: Option<Digest>) {
+        match part.file_name {
+            Some(file_name) => {
+                self.form.files.push(File {
+                    field_name: part.name,
+                    filename: file_name.decoded,
+                    raw_filename: file_name.raw,
+                    filename_altered: file_name.altered,
+                    content_type: part.content_type.unwrap_or(mime::APPLICATION_OCTET_STREAM),
+                    detected_content_type: part
+                        .detected_content_type
+                        .unwrap_or(mime::APPLICATION_OCTET_STREAM.essence_str()),
+                    content,
+                    digest,
+                    transfer_encoding: part.transfer_encoding,
+                    content_encoding: part.content_encoding,
+                    headers: part.headers,
+                });
+            }
+            None => {
+                let content = match content {
+                    FileContent::Memory(content) => content,
+                    FileContent::Spooled { .. } => unreachable!("text fields are never spooled"),
+                };
+                let charset = part
+                    .content_type
+                    .as_ref()
+                    .and_then(|mime| mime.get_param(mime::CHARSET))
+                    .and_then(|charset| Encoding::for_label(charset.as_str().as_bytes()));
+
+                // Resolved in `finish`, once the form's `_charset_` field is known.
+                self.form.fields.push(Field {
+                    name: part.name,
+                    value: OnceLock::new(),
+                    charset: UTF_8,
+                    raw: content,
+                    headers: part.headers,
+                });
+                self.charsets.push(charset);
+            }
+        }
+    }
+
+    pub fn finish(mut self, options: &Options) -> Result<Form, Error> {
+        resolve_charsets(&mut self.form.fields, &self.charsets);
+        self.form.validate(&options.schema)?;
+        Ok(self.form)
+    }
+}
+
+/// Collects every part, keeping text fields in memory and spooling large files to disk.
+pub(crate) struct FormCollector {
+    builder: FormBuilder,
+    current: Option<(PartMeta, SpoolBuffer)>,
+    max_memory_size: Option<u64>,
+    temp_dir: Option<PathBuf>,
+}
+
+impl FormCollector {
+    pub fn new(options: &Options) -> Self {
+        FormCollector {
+            builder: FormBuilder::default(),
+            current: None,
+            max_memory_size: options.max_memory_size,
+            temp_dir: options.temp_dir.clone(),
+        }
+    }
+}
+
+impl PartSink for FormCollector {
+    fn begin(&mut self, part: PartMeta) -> Result<(), Error> {
+        // Text fields are bounded by max_field_size instead and always stay in memory.
+        let threshold = if part.is_file() {
+            self.max_memory_size
+        } else {
+            None
+        };
+
+        let buffer = SpoolBuffer::new(threshold, self.temp_dir.clone());
+        self.current = Some((part, buffer));
+        Ok(())
+    }
+
+    fn data(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        if let Some((_, buffer)) = &mut self.current {
+            buffer.write(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, digest: Option<Digest>) -> Result<(), Error> {
+        if let Some((part, buffer)) = self.current.take() {
+            self.builder.push(part, buffer.finish()?, digest);
+        }
+        Ok(())
+    }
+
+    fn finish(self, options: &Options) -> Result<Form, Error> {
+        self.builder.finish(options)
+    }
+}
+
+/// Sets the charset that the raw value of each field is transcoded from to the one it
+/// declares, given in the same order as the fields, falling back to the form's `_charset_`
+/// field (RFC 7578 §4.6) and then UTF-8. Values are only transcoded once they are used.
+pub(crate) fn resolve_charsets(fields: &mut [Field], charsets: &[Option<&'static Encoding>]) {
+    let default_charset = fields
+        .iter()
+        .find(|field| field.name == "_charset_")
+        .and_then(|field| Encoding::for_label(&field.raw));
+
+    for (i, field) in fields.iter_mut().enumerate() {
+        field.charset = charsets
+            .get(i)
+            .copied()
+            .flatten()
+            .or(default_charset)
+            .unwrap_or(UTF_8);
+    }
+}
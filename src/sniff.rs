@@ -0,0 +1,257 @@
+use memchr::memmem;
+
+/// Number of bytes at the start of a file that are looked at to detect its content type.
+pub(crate) const SNIFF_LEN: usize = 4096;
+
+/// Content type of binary content that is not recognised.
+const UNKNOWN_BINARY: &str = "application/octet-stream";
+
+/// Magic numbers at a fixed offset, with the content type they identify.
+/// Checked in order, so longer signatures come before shorter ones sharing a prefix.
+/// Signatures shorter than 4 bytes are ignored for content that looks like text,
+/// so that e.g. a note starting with "MZ" is not taken for an executable.
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    // Images
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (8, b"WEBP", "image/webp"),
+    (0, b"BM", "image/bmp"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (4, b"ftypavif", "image/avif"),
+    (4, b"ftypheic", "image/heic"),
+    (4, b"ftypmif1", "image/heif"),
+    // Audio and video
+    (4, b"ftypisom", "video/mp4"),
+    (4, b"ftypmp42", "video/mp4"),
+    (4, b"ftypqt", "video/quicktime"),
+    (8, b"WAVE", "audio/wav"),
+    (8, b"AVI ", "video/x-msvideo"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "application/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"\x1aE\xdf\xa3", "video/webm"),
+    // Documents
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"%!PS", "application/postscript"),
+    (0, b"{\\rtf", "application/rtf"),
+    (
+        0,
+        b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+        "application/x-ole-storage",
+    ),
+    // Archives and compressed data
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    // Executables
+    (0, b"MZ", "application/vnd.microsoft.portable-executable"),
+    (0, b"\x7fELF", "application/x-elf"),
+    (0, b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+    (0, b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (0, b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\x00asm", "application/wasm"),
+];
+
+/// Content types of OpenDocument files, which are stored in their first zip entry.
+const OPEN_DOCUMENT_TYPES: &[&str] = &[
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+];
+
+/// Detects the content type of a file from the bytes at its start, up to `SNIFF_LEN` of them.
+/// Unrecognised content is `text/plain` if it looks like text and `application/octet-stream`
+/// otherwise, which is also the type of an empty file.
+pub(crate) fn sniff(prefix: &[u8]) -> &'static str {
+    let prefix = &prefix[..prefix.len().min(SNIFF_LEN)];
+    let text = !prefix.is_empty() && is_text(prefix);
+
+    if let Some(&(_, _, content_type)) = SIGNATURES.iter().find(|(offset, magic, _)| {
+        (magic.len() >= 4 || !text)
+            && prefix
+                .get(*offset..)
+                .is_some_and(|rest| rest.starts_with(magic))
+    }) {
+        return content_type;
+    }
+
+    if prefix.starts_with(b"PK\x03\x04") || prefix.starts_with(b"PK\x05\x06") {
+        return sniff_zip(prefix);
+    }
+
+    if !text {
+        return UNKNOWN_BINARY;
+    }
+    sniff_text(prefix)
+}
+
+/// Tells apart the formats that are zip archives from the names of their first entries.
+fn sniff_zip(prefix: &[u8]) -> &'static str {
+    // OpenDocument files start with an uncompressed `mimetype` entry holding their type.
+    if prefix.get(30..38) == Some(b"mimetype") {
+        let entry = &prefix[38..];
+        if let Some(content_type) = OPEN_DOCUMENT_TYPES
+            .iter()
+            .find(|content_type| entry.starts_with(content_type.as_bytes()))
+        {
+            return content_type;
+        }
+    }
+
+    let contains = |name: &[u8]| memmem::find(prefix, name).is_some();
+    if contains(b"word/") {
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+    } else if contains(b"xl/") {
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+    } else if contains(b"ppt/") {
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+    } else if contains(b"META-INF/MANIFEST.MF") {
+        "application/java-archive"
+    } else {
+        "application/zip"
+    }
+}
+
+/// Tells apart the text formats that are worth rejecting where plain text is expected:
+/// scripts, and markup that browsers may run scripts from.
+fn sniff_text(prefix: &[u8]) -> &'static str {
+    let text = prefix.trim_ascii_start();
+    let starts_with = |tag: &[u8]| {
+        text.get(..tag.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(tag))
+    };
+
+    if text.starts_with(b"#!") {
+        "text/x-shellscript"
+    } else if memmem::find(prefix, b"<svg").is_some()
+        && (starts_with(b"<svg") || starts_with(b"<?xml"))
+    {
+        "image/svg+xml"
+    } else if starts_with(b"<!doctype html") || starts_with(b"<html") || starts_with(b"<script") {
+        "text/html"
+    } else if starts_with(b"<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Returns true if the bytes are UTF-8 without control characters other than whitespace.
+/// A sequence cut short at the end of the prefix does not count against it.
+fn is_text(prefix: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(prefix) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            // Only the last character was cut short.
+            std::str::from_utf8(&prefix[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+
+    valid
+        .bytes()
+        .all(|b| !b.is_ascii_control() || matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b'))
+}
+
+/// Returns true if `content_type` matches `pattern`, which is either a type such as
+/// `image/png` or a wildcard such as `image/*` or `*/*`. Parameters are ignored, and
+/// the comparison is case-insensitive.
+pub(crate) fn matches(pattern: &str, content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let pattern = pattern.trim();
+
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(type_) => essence
+            .split('/')
+            .next()
+            .is_some_and(|essence_type| essence_type.eq_ignore_ascii_case(type_)),
+        None => essence.eq_ignore_ascii_case(pattern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MultipartError;
+    use crate::form::{parse_blocking, Form};
+    use crate::{Error, Options};
+
+    #[test]
+    fn signatures() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff(b"\x1f\x8b\x08\0"), "application/gzip");
+        // Short signatures are not trusted in text.
+        assert_eq!(sniff(b"MZ is a note"), "text/plain");
+        assert_eq!(
+            sniff(b"MZ\x90\0\x03\0"),
+            "application/vnd.microsoft.portable-executable"
+        );
+    }
+
+    #[test]
+    fn text_and_binary() {
+        assert_eq!(sniff(b""), "application/octet-stream");
+        assert_eq!(sniff(b"\0\x01\x02"), "application/octet-stream");
+        assert_eq!(sniff("plain café\r\n".as_bytes()), "text/plain");
+        // A character cut short by the end of the prefix is still text.
+        let text = format!("a{}", "é".repeat(SNIFF_LEN));
+        assert_eq!(sniff(text.as_bytes()), "text/plain");
+        assert_eq!(sniff(b"#!/bin/sh\nrm -rf /"), "text/x-shellscript");
+        assert_eq!(sniff(b"  <!DOCTYPE html><p>"), "text/html");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><svg/>"), "image/svg+xml");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><a/>"), "application/xml");
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches("image/png", "IMAGE/PNG"));
+        assert!(matches("image/*", "image/svg+xml"));
+        assert!(matches("*/*", "application/pdf"));
+        assert!(matches("text/plain", "text/plain; charset=utf-8"));
+        assert!(!matches("image/*", "application/pdf"));
+        assert!(!matches("image/png", "image/pngx"));
+    }
+
+    /// Parses a form of one file part of field `f`, with the disposition parameters and
+    /// content given, allowing only images for the field.
+    fn parse_file(parameters: &str, content: &str) -> Result<Form, Error> {
+        let body = format!(
+            "--X\r\nContent-Disposition: form-data; name=\"f\"{}\r\n\
+             Content-Type: application/octet-stream\r\n\r\n{}\r\n--X--\r\n",
+            parameters, content
+        );
+        let mut options = Options::default();
+        options
+            .allowed_content_types
+            .insert("f".to_string(), vec!["image/*".to_string()]);
+        parse_blocking("multipart/form-data; boundary=X", body, &options)
+    }
+
+    #[test]
+    fn no_file_chosen_is_not_checked() {
+        let form = parse_file("; filename=\"\"", "").unwrap();
+        assert_eq!(form.files().len(), 1);
+        assert_eq!(
+            form.files()[0].detected_content_type(),
+            "application/octet-stream"
+        );
+
+        // A named empty file, or content without a name, is still checked.
+        for (parameters, content) in [("; filename=\"a.png\"", ""), ("; filename=\"\"", "x")] {
+            let err = parse_file(parameters, content).unwrap_err();
+            assert_eq!(err.code(), MultipartError::DisallowedContentType);
+        }
+        assert!(parse_file("; filename=\"a.png\"", "GIF89a").is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::{ffi_status, Error, MultipartError};
+use crate::MultipartFile;
+use multer::bytes::Bytes;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use tempfile::{NamedTempFile, TempPath};
+
+/// Content of a file part once it has been read completely.
+#[derive(Debug)]
+pub(crate) enum FileContent {
+    Memory(Bytes),
+    // The temporary file is deleted when the path is dropped, unless cleanup is disabled.
+    Spooled { path: TempPath, len: u64 },
+}
+
+/// Buffers a file part in memory and moves it to a temporary file once it grows past the threshold.
+pub(crate) struct SpoolBuffer {
+    threshold: Option<u64>,
+    dir: Option<PathBuf>,
+    memory: Vec<u8>,
+    file: Option<BufWriter<NamedTempFile>>,
+    len: u64,
+}
+
+impl SpoolBuffer {
+    /// Creates a buffer that spools to `dir` (or the system temp directory) past `threshold` bytes.
+    /// A `None` threshold keeps everything in memory.
+    pub fn new(threshold: Option<u64>, dir: Option<PathBuf>) -> Self {
+        SpoolBuffer {
+            threshold,
+            dir,
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.len += chunk.len() as u64;
+
+        if self.file.is_none() && self.threshold.is_some_and(|threshold| self.len > threshold) {
+            let dir = self.dir.clone().unwrap_or_else(std::env::temp_dir);
+            let file = tempfile::Builder::new()
+                .prefix("multipart-")
+                .tempfile_in(dir)?;
+
+            let mut writer = BufWriter::new(file);
+            writer.write_all(&std::mem::take(&mut self.memory))?;
+            self.file = Some(writer);
+        }
+
+        match &mut self.file {
+            Some(writer) => writer.write_all(chunk),
+            None => {
+                self.memory.extend_from_slice(chunk);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<FileContent, Error> {
+        match self.file {
+            Some(writer) => {
+                let file = writer.into_inner().map_err(|err| err.into_error())?;
+                Ok(FileContent::Spooled {
+                    path: file.into_temp_path(),
+                    len: self.len,
+                })
+            }
+            None => Ok(FileContent::Memory(Bytes::from(self.memory))),
+        }
+    }
+}
+
+/// Borrows a path stored as a C string by the library.
+///
+/// # Safety
+/// `path` must point to a valid NUL-terminated string created from `OsStr::as_encoded_bytes`
+/// or from UTF-8, and must outlive the returned path.
+pub(crate) unsafe fn c_path<'a>(path: *const c_char) -> &'a Path {
+    Path::new(OsStr::from_encoded_bytes_unchecked(
+        CStr::from_ptr(path).to_bytes(),
+    ))
+}
+
+/// Moves the file at `from` to `to`, copying it when a rename is not possible,
+/// e.g. because the destination is on another filesystem.
+pub(crate) fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Writes the file to `dest_path`, taking ownership of it away from the form data.
+/// A spooled file is moved into place and `path` is updated to `dest_path`, so it is no longer
+/// deleted by `free_multipart_form_data`. An in-memory file has its content written to `dest_path`.
+/// Returns `Ok` on success, or the reason it failed.
+///
+/// # Safety
+/// `file` must be null or point to a file of a form data that has not been freed.
+/// `dest_path` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_file_persist(
+    file: *mut MultipartFile,
+    dest_path: *const c_char,
+) -> MultipartError {
+    ffi_status(|| {
+        let file = file
+            .as_mut()
+            .ok_or_else(|| Error::new(MultipartError::NullArgument, "file is null"))?;
+        if dest_path.is_null() {
+            return Err(Error::new(
+                MultipartError::NullArgument,
+                "destination path is null",
+            ));
+        }
+
+        let dest = CStr::from_ptr(dest_path);
+        let dest_str = dest.to_str().map_err(|_| {
+            Error::new(
+                MultipartError::InvalidUtf8,
+                "destination path is not valid UTF-8",
+            )
+        })?;
+
+        if file.path.is_null() {
+            let content = if file.content.is_null() {
+                &[][..]
+            } else {
+                std::slice::from_raw_parts(file.content, file.content_length)
+            };
+            fs::write(dest_str, content)?;
+            return Ok(());
+        }
+
+        move_file(c_path(file.path), Path::new(dest_str))?;
+
+        // Only a path set by an earlier persist is owned separately; the others are
+        // part of the form data's allocation.
+        if !file.is_temporary {
+            drop(CString::from_raw(file.path as *mut c_char));
+        }
+        file.path = CString::from(dest).into_raw();
+        file.is_temporary = false;
+        Ok(())
+    })
+}
@@ -0,0 +1,240 @@
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::{DecodeError, Engine as _};
+use multer::bytes::Bytes;
+
+/// Standard base64, accepting content with or without its final padding as senders differ.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Decodes the content of a part sent with a `Content-Transfer-Encoding` (RFC 2045 §6)
+/// chunk by chunk, holding back the bytes that cannot be decoded until the next chunk.
+pub(crate) enum Decoder {
+    Base64(Vec<u8>),
+    QuotedPrintable(Vec<u8>),
+}
+
+impl Decoder {
+    /// Returns a decoder for the encoding, or `None` if the content is sent as it is,
+    /// as with `7bit`, `8bit` and `binary`.
+    pub fn new(encoding: &str) -> Option<Self> {
+        if encoding.eq_ignore_ascii_case("base64") {
+            Some(Decoder::Base64(Vec::new()))
+        } else if encoding.eq_ignore_ascii_case("quoted-printable") {
+            Some(Decoder::QuotedPrintable(Vec::new()))
+        } else {
+            None
+        }
+    }
+
+    /// Decodes as much of the content received so far as possible, and everything that was
+    /// held back once the `last` chunk has been received.
+    pub fn decode(&mut self, chunk: &[u8], last: bool) -> Result<Bytes, DecodeError> {
+        match self {
+            Decoder::Base64(pending) => {
+                // Line breaks are allowed anywhere, so only whole groups of 4 characters are
+                // decoded until the end.
+                pending.extend(chunk.iter().filter(|b| !b.is_ascii_whitespace()));
+                let len = if last {
+                    pending.len()
+                } else {
+                    pending.len() / 4 * 4
+                };
+                let decoded = BASE64.decode(&pending[..len])?;
+                pending.drain(..len);
+                Ok(decoded.into())
+            }
+            Decoder::QuotedPrintable(pending) => {
+                pending.extend_from_slice(chunk);
+                let mut decoded = Vec::with_capacity(pending.len());
+                let used = quoted_printable(pending, last, &mut decoded);
+                pending.drain(..used);
+                Ok(decoded.into())
+            }
+        }
+    }
+}
+
+/// Decodes quoted-printable content into `out` and returns the number of bytes used.
+/// Unless this is the `last` of the content, an escape or a run of spaces cut short at the
+/// end is left for the next call.
+/// Soft line breaks are removed, as is whitespace at the end of a line, which may have been
+/// added in transport. Like most decoders, a malformed escape is kept as it is.
+fn quoted_printable(data: &[u8], last: bool, out: &mut Vec<u8>) -> usize {
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                let rest = &data[i + 1..];
+                if !last && rest.len() < 2 {
+                    return i;
+                }
+                match rest {
+                    // Soft line breaks. At the very end of the content, the line break that
+                    // follows belongs to the boundary.
+                    [b'\r', b'\n', ..] => i += 3,
+                    [b'\n', ..] => i += 2,
+                    [] => i += 1,
+                    [high, low, ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                        out.push(hex(*high) << 4 | hex(*low));
+                        i += 3;
+                    }
+                    _ => {
+                        out.push(b'=');
+                        i += 1;
+                    }
+                }
+            }
+            b' ' | b'\t' => {
+                let end = data[i..]
+                    .iter()
+                    .position(|&b| b != b' ' && b != b'\t')
+                    .map_or(data.len(), |len| i + len);
+                let after = &data[end..];
+                if !last && (after.is_empty() || after == b"\r") {
+                    return i;
+                }
+                if !matches!(after, [] | [b'\n', ..] | [b'\r', b'\n', ..]) {
+                    out.extend_from_slice(&data[i..end]);
+                }
+                i = end;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    i
+}
+
+fn hex(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes content sent in the given chunks.
+    fn decode(encoding: &str, chunks: &[&[u8]]) -> Result<Vec<u8>, DecodeError> {
+        let mut decoder = Decoder::new(encoding).unwrap();
+        let mut decoded = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            decoded.extend_from_slice(&decoder.decode(chunk, i + 1 == chunks.len())?);
+        }
+        Ok(decoded)
+    }
+
+    /// Decodes the content whole and split in two at every position, checking that every
+    /// split decodes the same.
+    fn decode_split(encoding: &str, content: &[u8]) -> Vec<u8> {
+        let whole = decode(encoding, &[content]).unwrap();
+        for at in 0..=content.len() {
+            let (head, tail) = content.split_at(at);
+            assert_eq!(
+                decode(encoding, &[head, tail]).unwrap(),
+                whole,
+                "split at {}",
+                at
+            );
+        }
+        whole
+    }
+
+    #[test]
+    fn quoted_printable_escapes() {
+        assert_eq!(
+            decode_split("quoted-printable", b"caf=C3=A9 =3D 100=25"),
+            "café = 100%".as_bytes()
+        );
+        // Lowercase digits are accepted, and malformed escapes are kept as they are.
+        assert_eq!(decode_split("quoted-printable", b"=e9=zz=4"), b"\xe9=zz=4");
+    }
+
+    #[test]
+    fn quoted_printable_escapes_split_across_chunks() {
+        let chunks: &[&[u8]] = &[b"a=", b"C", b"3=A", b"9b"];
+        assert_eq!(
+            decode("quoted-printable", chunks).unwrap(),
+            "aéb".as_bytes()
+        );
+    }
+
+    #[test]
+    fn quoted_printable_soft_line_breaks() {
+        assert_eq!(
+            decode_split("quoted-printable", b"long=\r\nline=\nend=\r\n"),
+            b"longlineend"
+        );
+        assert_eq!(
+            decode_split("quoted-printable", b"keep =\r\n space"),
+            b"keep  space"
+        );
+
+        // A soft line break at the end of a chunk is held back until its line break arrives.
+        let chunks: &[&[u8]] = &[b"ab=", b"\r", b"\ncd"];
+        assert_eq!(decode("quoted-printable", chunks).unwrap(), b"abcd");
+        let chunks: &[&[u8]] = &[b"ab=", b"\r\ncd"];
+        assert_eq!(decode("quoted-printable", chunks).unwrap(), b"abcd");
+
+        // A lone `=` at the very end of the content is a soft line break.
+        assert_eq!(decode("quoted-printable", &[b"ab="]).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn quoted_printable_trailing_whitespace() {
+        assert_eq!(
+            decode_split("quoted-printable", b"a \t \r\nb\t\nc d  "),
+            b"a\r\nb\nc d"
+        );
+
+        // Whitespace at the end of a chunk is held back until it is known to end a line.
+        let chunks: &[&[u8]] = &[b"a  ", b"\r", b"\nb  ", b"c"];
+        assert_eq!(decode("quoted-printable", chunks).unwrap(), b"a\r\nb  c");
+    }
+
+    #[test]
+    fn base64_groups_split_by_line_breaks() {
+        assert_eq!(
+            decode_split("base64", b"aGVs\r\nbG8s\r\nIHdv\r\ncmxk\r\n"),
+            b"hello, world"
+        );
+        assert_eq!(
+            decode_split("base64", b"aG\r\nVsb\nG8s IHdvcm\r\nxk"),
+            b"hello, world"
+        );
+
+        let chunks: &[&[u8]] = &[b"aG", b"V", b"s\r", b"\nbG8=\r\n"];
+        assert_eq!(decode("base64", chunks).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn base64_padding_is_optional() {
+        assert_eq!(decode_split("base64", b"aGk="), b"hi");
+        assert_eq!(decode_split("base64", b"aGk"), b"hi");
+    }
+
+    #[test]
+    fn malformed_base64() {
+        assert!(decode("base64", &[b"aGk*"]).is_err());
+        assert!(decode("base64", &[b"aGk=aGk="]).is_err());
+        assert!(decode("base64", &[b"a"]).is_err());
+    }
+
+    #[test]
+    fn identity_encodings() {
+        for encoding in ["7bit", "8bit", "binary", "x-unknown"] {
+            assert!(Decoder::new(encoding).is_none());
+        }
+        assert!(Decoder::new("BASE64").is_some());
+        assert!(Decoder::new("Quoted-Printable").is_some());
+    }
+}
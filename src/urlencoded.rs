@@ -0,0 +1,129 @@
+use crate::decompress;
+use crate::error::{Error, MultipartError};
+use crate::form::{Field, Form};
+use crate::options::{Options, ParseOptions};
+use crate::sink::resolve_charsets;
+use crate::{body_slice, ffi_parse, FormData};
+use encoding_rs::UTF_8;
+use multer::bytes::Bytes;
+use percent_encoding::percent_decode;
+use std::sync::OnceLock;
+
+/// Decodes a name or value: `+` is a space and `%XX` is the byte it encodes.
+fn decode(encoded: &[u8]) -> Vec<u8> {
+    let spaced: Vec<u8> = encoded
+        .iter()
+        .map(|&b| if b == b'+' { b' ' } else { b })
+        .collect();
+    percent_decode(&spaced).collect()
+}
+
+/// Parses an `application/x-www-form-urlencoded` body into a form without files.
+/// Pairs are kept in the order they appear, including duplicate names.
+/// A body compressed with the `content_encoding` option is decompressed first.
+pub(crate) fn parse_urlencoded(body: &[u8], options: &Options) -> Result<Form, Error> {
+    let decompressed;
+    let body = match decompress::body_decompressor(options)? {
+        Some(mut decompressor) => {
+            decompressed = decompressor.decompress(body, true)?;
+            &decompressed[..]
+        }
+        None => body,
+    };
+
+    if let Some(limit) = options
+        .max_total_size
+        .filter(|&limit| body.len() as u64 > limit)
+    {
+        return Err(Error::new(
+            MultipartError::SizeLimitExceeded,
+            format!("stream size exceeded limit: {} bytes", limit),
+        ));
+    }
+
+    let mut form = Form::default();
+
+    for pair in body.split(|&b| b == b'&').filter(|pair| !pair.is_empty()) {
+        if let Some(max) = options.max_parts.filter(|&max| form.fields.len() >= max) {
+            return Err(Error::new(
+                MultipartError::TooManyParts,
+                format!("form data has more than {} parts", max),
+            ));
+        }
+
+        let (name, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &[][..]),
+        };
+
+        let name = String::from_utf8(decode(name)).map_err(|_| {
+            Error::new(MultipartError::InvalidUtf8, "field name is not valid UTF-8")
+        })?;
+
+        if let Some(allowed_fields) = &options.allowed_fields {
+            if !allowed_fields.contains(&name) {
+                return Err(Error::new(
+                    MultipartError::UnknownField,
+                    format!("unknown field received: {:?}", name),
+                ));
+            }
+        }
+
+        let raw = decode(value);
+        if let Some(limit) = options
+            .max_field_size
+            .filter(|&limit| raw.len() as u64 > limit)
+        {
+            return Err(Error::new(
+                MultipartError::SizeLimitExceeded,
+                format!("field {:?} exceeded the size limit: {} bytes", name, limit),
+            ));
+        }
+
+        form.fields.push(Field {
+            name,
+            value: OnceLock::new(),
+            charset: UTF_8,
+            raw: Bytes::from(raw),
+            headers: Vec::new(),
+        });
+    }
+
+    // Urlencoded pairs carry no charset of their own, only the form's `_charset_`.
+    resolve_charsets(&mut form.fields, &[]);
+    form.validate(&options.schema)?;
+    Ok(form)
+}
+
+/// Parses the `application/x-www-form-urlencoded` data from the `len` bytes starting at `body`.
+/// The form data has no files; every pair becomes a field, in order, including duplicate names.
+/// Returns null on failure; see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the form data by calling `free_multipart_form_data`.
+///
+/// # Safety
+/// `body` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn parse_urlencoded_form_data(body: *const u8, len: usize) -> *mut FormData {
+    parse_urlencoded_form_data_with_options(body, len, std::ptr::null())
+}
+
+/// Parses the urlencoded data like `parse_urlencoded_form_data`, applying `options`.
+/// A null `options` applies no limits. `max_field_size` bounds each decoded value,
+/// `max_parts` the number of pairs, and options that only apply to multipart bodies are ignored.
+///
+/// # Safety
+/// `body` must be null or point to at least `len` readable bytes.
+/// `options` must be null or point to a valid `ParseOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn parse_urlencoded_form_data_with_options(
+    body: *const u8,
+    len: usize,
+    options: *const ParseOptions,
+) -> *mut FormData {
+    ffi_parse(|| {
+        let body = body_slice(body, len)?;
+        let options = Options::from_ffi(options)?;
+
+        parse_urlencoded(body, &options)
+    })
+}
@@ -0,0 +1,545 @@
+use crate::boundary::is_valid_boundary;
+use crate::error::{ffi_guard, ffi_status, Error, MultipartError};
+use mime::Mime;
+use multer::bytes::Bytes;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+/// Size of the chunks read from files added by path.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the content of a part comes from.
+#[derive(Clone, Debug)]
+enum Source {
+    Memory(Bytes),
+    Path(PathBuf),
+}
+
+/// A part of the body, with its headers already encoded.
+#[derive(Clone, Debug)]
+struct Part {
+    headers: Bytes,
+    content: Source,
+}
+
+/// Builds a `multipart/form-data` body, the inverse of parsing one.
+/// Opaque to C; created by `multipart_writer_new`.
+pub struct MultipartWriter {
+    // Kept as C strings so that C callers can borrow them for the lifetime of the writer.
+    boundary: CString,
+    content_type: CString,
+    parts: Vec<Part>,
+    // Chunks handed out by `multipart_writer_next_chunk`; the current one must outlive the call.
+    reader: Option<(Chunks, Bytes)>,
+}
+
+impl MultipartWriter {
+    /// Creates a writer with a random boundary.
+    pub fn new() -> Self {
+        let boundary = format!(
+            "----FormBoundary{}",
+            std::iter::repeat_with(fastrand::alphanumeric)
+                .take(24)
+                .collect::<String>()
+        );
+
+        // Only alphanumerics and dashes, so neither invalid nor containing a NUL.
+        Self::with_boundary(&boundary).expect("generated boundary is valid")
+    }
+
+    /// Creates a writer with the given boundary, which must be valid per RFC 2046.
+    pub fn with_boundary(boundary: &str) -> Result<Self, Error> {
+        if !is_valid_boundary(boundary) {
+            return Err(Error::new(
+                MultipartError::MissingBoundary,
+                "invalid multipart boundary",
+            ));
+        }
+
+        // Boundaries with characters that are not allowed in a token must be quoted.
+        let is_token = boundary
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'\'' | b'+' | b'_' | b'-' | b'.'));
+        let content_type = if is_token {
+            format!("multipart/form-data; boundary={}", boundary)
+        } else {
+            format!("multipart/form-data; boundary=\"{}\"", boundary)
+        };
+
+        Ok(MultipartWriter {
+            boundary: CString::new(boundary)?,
+            content_type: CString::new(content_type)?,
+            parts: Vec::new(),
+            reader: None,
+        })
+    }
+
+    pub fn boundary(&self) -> &str {
+        self.boundary.to_str().unwrap_or_default()
+    }
+
+    /// The full value for the Content-Type header of the request.
+    pub fn content_type(&self) -> &str {
+        self.content_type.to_str().unwrap_or_default()
+    }
+
+    /// Adds a text field.
+    pub fn add_field(&mut self, name: &str, value: impl Into<Bytes>) {
+        let headers = format!(
+            "Content-Disposition: form-data; name=\"{}\"\r\n",
+            escape(name)
+        );
+        self.push(headers, Source::Memory(value.into()));
+    }
+
+    /// Adds a file whose content is already in memory.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: &Mime,
+        content: impl Into<Bytes>,
+    ) {
+        self.push(
+            file_headers(name, filename, content_type),
+            Source::Memory(content.into()),
+        );
+    }
+
+    /// Adds a file read from `path` when the body is generated. The filename defaults to
+    /// the last component of the path and the content type to `application/octet-stream`.
+    /// Fails if `path` is not a readable file.
+    pub fn add_file_path(
+        &mut self,
+        name: &str,
+        path: impl AsRef<Path>,
+        filename: Option<&str>,
+        content_type: Option<&Mime>,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        if !fs::metadata(path)?.is_file() {
+            return Err(Error::new(
+                MultipartError::Io,
+                format!("{} is not a file", path.display()),
+            ));
+        }
+
+        let default_filename = path.file_name().map(|name| name.to_string_lossy());
+        let filename = filename.or(default_filename.as_deref()).unwrap_or_default();
+        let content_type = content_type.unwrap_or(&mime::APPLICATION_OCTET_STREAM);
+
+        self.push(
+            file_headers(name, filename, content_type),
+            Source::Path(path.to_path_buf()),
+        );
+        Ok(())
+    }
+
+    fn push(&mut self, headers: String, content: Source) {
+        let headers = format!("--{}\r\n{}\r\n", self.boundary(), headers);
+
+        self.parts.push(Part {
+            headers: Bytes::from(headers),
+            content,
+        });
+    }
+
+    /// Returns the body chunk by chunk, reading files added by path as it goes.
+    /// Parts added afterwards are not included.
+    pub fn chunks(&self) -> Chunks {
+        let mut segments = VecDeque::new();
+        for part in &self.parts {
+            segments.push_back(Source::Memory(part.headers.clone()));
+            segments.push_back(part.content.clone());
+            segments.push_back(Source::Memory(Bytes::from_static(b"\r\n")));
+        }
+        segments.push_back(Source::Memory(Bytes::from(format!(
+            "--{}--\r\n",
+            self.boundary()
+        ))));
+
+        Chunks {
+            segments,
+            file: None,
+        }
+    }
+
+    /// Returns the complete body.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut body = Vec::new();
+        for chunk in self.chunks() {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body)
+    }
+}
+
+impl Default for MultipartWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the chunks of a body built by `MultipartWriter::chunks`.
+/// Stops after the first error.
+pub struct Chunks {
+    segments: VecDeque<Source>,
+    file: Option<fs::File>,
+}
+
+impl Iterator for Chunks {
+    type Item = Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = &mut self.file {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                match file.read(&mut chunk) {
+                    Ok(0) => self.file = None,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        return Some(Ok(Bytes::from(chunk)));
+                    }
+                    Err(err) => return Some(Err(self.fail(err))),
+                }
+                continue;
+            }
+
+            match self.segments.pop_front()? {
+                Source::Memory(bytes) if bytes.is_empty() => {}
+                Source::Memory(bytes) => return Some(Ok(bytes)),
+                Source::Path(path) => match fs::File::open(&path) {
+                    Ok(file) => self.file = Some(file),
+                    Err(err) => return Some(Err(self.fail(err))),
+                },
+            }
+        }
+    }
+}
+
+impl Chunks {
+    fn fail(&mut self, err: std::io::Error) -> Error {
+        self.segments.clear();
+        self.file = None;
+        err.into()
+    }
+}
+
+/// Escapes a name or filename for a quoted Content-Disposition parameter the way browsers do:
+/// `"`, CR and LF are percent-encoded.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn file_headers(name: &str, filename: &str, content_type: &Mime) -> String {
+    format!(
+        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n",
+        escape(name),
+        escape(filename),
+        content_type
+    )
+}
+
+/// Borrows a string argument passed from C, naming it in the error if it is null or not UTF-8.
+///
+/// # Safety
+/// `arg` must be null or point to a valid NUL-terminated string that outlives the returned string.
+unsafe fn str_arg<'a>(arg: *const c_char, what: &str) -> Result<&'a str, Error> {
+    if arg.is_null() {
+        return Err(Error::new(
+            MultipartError::NullArgument,
+            format!("{} is null", what),
+        ));
+    }
+
+    CStr::from_ptr(arg).to_str().map_err(|_| {
+        Error::new(
+            MultipartError::InvalidUtf8,
+            format!("{} is not valid UTF-8", what),
+        )
+    })
+}
+
+/// Borrows an optional content type passed from C.
+///
+/// # Safety
+/// `content_type` must be null or point to a valid NUL-terminated string.
+unsafe fn content_type_arg(content_type: *const c_char) -> Result<Option<Mime>, Error> {
+    if content_type.is_null() {
+        return Ok(None);
+    }
+
+    let content_type = str_arg(content_type, "content type")?;
+    content_type.parse().map(Some).map_err(|_| {
+        Error::new(
+            MultipartError::InvalidContentType,
+            format!("invalid content type {:?}", content_type),
+        )
+    })
+}
+
+/// Borrows the writer passed from C.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+unsafe fn writer_arg<'a>(writer: *mut MultipartWriter) -> Result<&'a mut MultipartWriter, Error> {
+    writer
+        .as_mut()
+        .ok_or_else(|| Error::new(MultipartError::NullArgument, "writer is null"))
+}
+
+/// Creates a writer for a multipart body with a random boundary.
+/// The writer must be released with `multipart_writer_free`.
+#[no_mangle]
+pub extern "C" fn multipart_writer_new() -> *mut MultipartWriter {
+    Box::into_raw(Box::new(MultipartWriter::new()))
+}
+
+/// Creates a writer like `multipart_writer_new` with the given boundary instead of a random one.
+/// Returns null if the boundary is not valid per RFC 2046; see `multipart_last_error` for the reason.
+///
+/// # Safety
+/// `boundary` must be null or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_new_with_boundary(
+    boundary: *const c_char,
+) -> *mut MultipartWriter {
+    let writer = ffi_guard(|| MultipartWriter::with_boundary(str_arg(boundary, "boundary")?));
+
+    writer.map_or(std::ptr::null_mut(), |writer| {
+        Box::into_raw(Box::new(writer))
+    })
+}
+
+/// Returns the boundary of the writer, without the leading `--`.
+/// The string is owned by the writer.
+///
+/// # Safety
+/// `writer` must be a pointer returned by `multipart_writer_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_boundary(
+    writer: *const MultipartWriter,
+) -> *const c_char {
+    writer
+        .as_ref()
+        .map_or(std::ptr::null(), |writer| writer.boundary.as_ptr())
+}
+
+/// Returns the full Content-Type header value for the body, including the boundary.
+/// The string is owned by the writer.
+///
+/// # Safety
+/// `writer` must be a pointer returned by `multipart_writer_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_content_type(
+    writer: *const MultipartWriter,
+) -> *const c_char {
+    writer
+        .as_ref()
+        .map_or(std::ptr::null(), |writer| writer.content_type.as_ptr())
+}
+
+/// Adds a text field with the `len` bytes starting at `value`. The bytes are copied.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+/// `name` must be null or point to a valid NUL-terminated string.
+/// `value` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_add_field(
+    writer: *mut MultipartWriter,
+    name: *const c_char,
+    value: *const u8,
+    len: usize,
+) -> MultipartError {
+    ffi_status(|| {
+        let writer = writer_arg(writer)?;
+        let name = str_arg(name, "name")?;
+        if value.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "value is null"));
+        }
+
+        writer.add_field(
+            name,
+            Bytes::copy_from_slice(std::slice::from_raw_parts(value, len)),
+        );
+        Ok(())
+    })
+}
+
+/// Adds a file with the `len` bytes starting at `content`. The bytes are copied.
+/// A null `content_type` defaults to `application/octet-stream`.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+/// `name`, `filename` and `content_type` must be null or point to valid NUL-terminated strings.
+/// `content` must be null or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_add_file(
+    writer: *mut MultipartWriter,
+    name: *const c_char,
+    filename: *const c_char,
+    content_type: *const c_char,
+    content: *const u8,
+    len: usize,
+) -> MultipartError {
+    ffi_status(|| {
+        let writer = writer_arg(writer)?;
+        let name = str_arg(name, "name")?;
+        let filename = str_arg(filename, "filename")?;
+        let content_type =
+            content_type_arg(content_type)?.unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        if content.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "content is null"));
+        }
+
+        writer.add_file(
+            name,
+            filename,
+            &content_type,
+            Bytes::copy_from_slice(std::slice::from_raw_parts(content, len)),
+        );
+        Ok(())
+    })
+}
+
+/// Adds a file that is read from `path` when the body is generated.
+/// A null `filename` defaults to the last component of the path, and a null
+/// `content_type` to `application/octet-stream`. Fails with `Io` if `path` is not a readable file.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+/// `name`, `path`, `filename` and `content_type` must be null or point to valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_add_file_path(
+    writer: *mut MultipartWriter,
+    name: *const c_char,
+    path: *const c_char,
+    filename: *const c_char,
+    content_type: *const c_char,
+) -> MultipartError {
+    ffi_status(|| {
+        let writer = writer_arg(writer)?;
+        let name = str_arg(name, "name")?;
+        let path = str_arg(path, "path")?;
+        let filename = if filename.is_null() {
+            None
+        } else {
+            Some(str_arg(filename, "filename")?)
+        };
+        let content_type = content_type_arg(content_type)?;
+
+        writer.add_file_path(name, path, filename, content_type.as_ref())
+    })
+}
+
+/// Generates the complete body and stores its length in `len`.
+/// Returns null on failure, e.g. if a file added by path can no longer be read;
+/// see `multipart_last_error` for the reason.
+/// The caller is responsible for freeing the body by calling `multipart_buffer_free`.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+/// `len` must be null or point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_to_buffer(
+    writer: *const MultipartWriter,
+    len: *mut usize,
+) -> *mut u8 {
+    let body = ffi_guard(|| {
+        let writer = writer
+            .as_ref()
+            .ok_or_else(|| Error::new(MultipartError::NullArgument, "writer is null"))?;
+        if len.is_null() {
+            return Err(Error::new(MultipartError::NullArgument, "len is null"));
+        }
+
+        writer.to_bytes()
+    });
+
+    match body {
+        Ok(body) => {
+            // Boxed slices have capacity == length, which multipart_buffer_free relies on.
+            let body = body.into_boxed_slice();
+            *len = body.len();
+            Box::into_raw(body) as *mut u8
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a body returned by `multipart_writer_to_buffer`. If the body is null, does nothing.
+///
+/// # Safety
+/// `buffer` must be null or a pointer returned by `multipart_writer_to_buffer` together with
+/// its `len`, and must not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_buffer_free(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(Vec::from_raw_parts(buffer, len, len));
+    }
+}
+
+/// Generates the next chunk of the body, storing it in `data` and its length in `len`.
+/// The chunk is owned by the writer and stays valid until the next call.
+/// At the end of the body `len` is set to 0, after which the next call starts over.
+/// Parts added while the body is being generated are only included once it starts over.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+/// `data` and `len` must be null or point to writable locations.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_next_chunk(
+    writer: *mut MultipartWriter,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> MultipartError {
+    ffi_status(|| {
+        let writer = writer_arg(writer)?;
+        if data.is_null() || len.is_null() {
+            return Err(Error::new(
+                MultipartError::NullArgument,
+                "data or len is null",
+            ));
+        }
+
+        let (mut chunks, _) = writer
+            .reader
+            .take()
+            .unwrap_or_else(|| (writer.chunks(), Bytes::new()));
+
+        match chunks.next() {
+            Some(Ok(chunk)) => {
+                // The chunk is kept in the writer so that the pointer stays valid.
+                *data = chunk.as_ptr();
+                *len = chunk.len();
+                writer.reader = Some((chunks, chunk));
+                Ok(())
+            }
+            Some(Err(err)) => Err(err),
+            None => {
+                *data = std::ptr::null();
+                *len = 0;
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Releases the writer. If the writer is null, does nothing.
+///
+/// # Safety
+/// `writer` must be null or a pointer returned by `multipart_writer_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn multipart_writer_free(writer: *mut MultipartWriter) {
+    if !writer.is_null() {
+        drop(Box::from_raw(writer));
+    }
+}